@@ -1,89 +1,370 @@
-use rand::{thread_rng, Rng};
+use glam::Vec3;
 
-use crate::{shape::*, intersection::{Inter, Intersection, Traceable}};
+use crate::{shape::*, intersection::{Inter, Traceable}};
 
+/// Number of buckets along each axis in which the surface area heuristic looks for splits
+const BINS: usize = 12;
+
+/// Most primitives kept in a leaf when splitting would cost more than testing them all
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Cost of visiting a node, relative to intersecting a primitive
+const TRAVERSAL_COST: f32 = 0.125;
+
+/// Deepest a hierarchy gets, bounding the traversal stack
+const MAX_DEPTH: usize = 64;
+
+/// Node of the flattened hierarchy, 32 bytes so that two fit in a cache line
+///
+/// Nodes are laid out depth first, the left child of an inner node following it.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Node {
+    min: Vec3,
+    /// Index of the right child of an inner node, or of the first shape of a leaf
+    offset: u32,
+    max: Vec3,
+    /// Number of shapes of a leaf, zero for inner nodes
+    count: u32
+}
+
+const _: () = assert!(std::mem::size_of::<Node>() == 32);
+
+impl Node {
+    fn bound(&self) -> Rect {
+        Rect { min: self.min, max: self.max }
+    }
+
+    /// Distance along the ray at which it enters the box, in units of its direction, if it does before `tmax`
+    fn entry(&self, start: Vec3, inv_dir: Vec3, tmax: f32) -> Option<f32> {
+        let t1 = (self.min - start) * inv_dir;
+        let t2 = (self.max - start) * inv_dir;
+
+        let near = t1.min(t2).max_element().max(0.0);
+        let far = t1.max(t2).min_element().min(tmax);
+
+        (near <= far).then_some(near)
+    }
+}
+
+/// Bounding volume hierarchy over shapes, stored as a flat array of nodes
+///
+/// Scenes are traced through one over references to their shapes, and each [`Mesh`](crate::mesh::Mesh) owns one over its triangles.
+///
+/// Shapes without finite bounds, like planes, would make the boxes of all their ancestors infinite.
+/// They are kept out of the hierarchy and tested by every ray instead.
 #[derive(Debug)]
-pub struct Bvh<'a> {
-    pub lhs: Option<Box<Bvh<'a>>>,
-    pub rhs: Option<Box<Bvh<'a>>>,
-    pub bound: Rect,
-    pub shape: Option<&'a dyn Traceable>
+pub struct Bvh<T> {
+    nodes: Vec<Node>,
+    /// Shapes ordered so that each leaf refers to a run of them
+    shapes: Vec<T>,
+    unbounded: Vec<T>
 }
 
-impl<'a> Bvh<'a> {
-    pub fn new(bound: Rect, shape: &'a dyn Traceable) -> Self {
-        Bvh {
-            lhs: None,
-            rhs: None,
-            bound,
-            shape: Some(shape)
+impl<T: Traceable> Bvh<T> {
+    /// Sets the unbounded shapes aside and builds the hierarchy of the others with `build`, when there are any
+    fn with_builder(mut shapes: Vec<T>, build: impl FnOnce(&mut Vec<Node>, &mut [T])) -> Bvh<T> {
+        let bounded = itertools::partition(shapes.iter_mut(), |shape| shape.bounding_box().is_finite());
+        let unbounded = shapes.split_off(bounded);
+
+        let mut nodes = Vec::with_capacity(2 * shapes.len());
+        if !shapes.is_empty() {
+            build(&mut nodes, &mut shapes);
         }
+
+        Bvh { nodes, shapes, unbounded }
     }
 
-    fn from_child(lhs: Box<Bvh<'a>>, rhs: Box<Bvh<'a>>) -> Self {
-        let mut bound = lhs.bound;
-        bound.min.x = bound.min.x.min(rhs.bound.min.x);
-        bound.min.y = bound.min.y.min(rhs.bound.min.y);
-        bound.min.z = bound.min.z.min(rhs.bound.min.z);
-        bound.max.x = bound.max.x.max(rhs.bound.max.x);
-        bound.max.y = bound.max.y.max(rhs.bound.max.y);
-        bound.max.z = bound.max.z.max(rhs.bound.max.z);
-        
-        Bvh {
-            lhs: Some(lhs),
-            rhs: Some(rhs),
-            bound,
-            shape: None
+    fn push_leaf(nodes: &mut Vec<Node>, shapes: &[T], start: usize) {
+        let bound = shapes.iter().fold(Rect::empty(), |bound, shape| bound.union(shape.bounding_box()));
+
+        nodes.push(Node { min: bound.min, offset: start as u32, max: bound.max, count: shapes.len() as u32 });
+    }
+
+    /// Pushes an inner node and the children built by `build`, called with the side and its position among the shapes
+    fn push_inner<'b>(nodes: &mut Vec<Node>, left: &'b mut [T], right: &'b mut [T], start: usize,
+                      mut build: impl FnMut(&mut Vec<Node>, &'b mut [T], usize)) {
+        let index = nodes.len();
+        nodes.push(Node { min: Vec3::ZERO, offset: 0, max: Vec3::ZERO, count: 0 });
+
+        let middle = start + left.len();
+        build(nodes, left, start);
+        let offset = nodes.len();
+        build(nodes, right, middle);
+
+        let bound = nodes[index + 1].bound().union(nodes[offset].bound());
+        nodes[index] = Node { min: bound.min, offset: offset as u32, max: bound.max, count: 0 };
+    }
+
+    /// Splits the shapes at their median, alternating between the x and y axes, with one shape per leaf
+    ///
+    /// Superseded by [`Bvh::build`], and kept to compare against.
+    pub fn construct(shapes: Vec<T>, dim: usize) -> Bvh<T> {
+        Bvh::with_builder(shapes, |nodes, shapes| Bvh::construct_node(nodes, shapes, 0, dim))
+    }
+
+    fn construct_node(nodes: &mut Vec<Node>, shapes: &mut [T], start: usize, dim: usize) {
+        if shapes.len() == 1 {
+            return Bvh::push_leaf(nodes, shapes, start);
         }
+
+        shapes.sort_by(|a, b| {
+            if dim.is_multiple_of(2) {
+                a.position().x.partial_cmp(&b.position().x).unwrap()
+            }
+            else {
+                a.position().y.partial_cmp(&b.position().y).unwrap()
+            }
+        });
+
+        let ( left, right ) = shapes.split_at_mut( shapes.len() / 2 );
+
+        Bvh::push_inner(nodes, left, right, start, |nodes, shapes, start| Bvh::construct_node(nodes, shapes, start, dim + 1));
     }
 
-    pub fn construct<'b>(shapes: &'b mut [&'a dyn Traceable], dim: usize) -> Bvh<'a> {
-        if shapes.is_empty() { panic!("Empty vector"); }
-        else if shapes.len() == 1 {
-            let shape = *shapes.first().unwrap();
-            Bvh::new(shape.bounding_box(), shape)
-            // Bvh::new(Rect::infinite(), shape)
+    /// Builds the hierarchy with the surface area heuristic, splitting where rays are expected to test the fewest primitives
+    ///
+    /// Candidate splits lie between [`BINS`] buckets of the centroids along each axis.
+    pub fn build(shapes: Vec<T>) -> Bvh<T> {
+        Bvh::with_builder(shapes, |nodes, shapes| Bvh::build_node(nodes, shapes, 0, 1))
+    }
+
+    fn build_node(nodes: &mut Vec<Node>, shapes: &mut [T], start: usize, depth: usize) {
+        // Past the deepest level the traversal can handle, whatever is left goes in a leaf
+        if shapes.len() == 1 || depth == MAX_DEPTH {
+            return Bvh::push_leaf(nodes, shapes, start);
         }
-        else {
-            shapes.sort_by(|a, b| {
-                if dim % 2 == 0 {
-                    a.position().x.partial_cmp(&b.position().x).unwrap()
-                }
-                else {
-                    a.position().y.partial_cmp(&b.position().y).unwrap()
-                }
-            });
 
-            let ( left, right ) = shapes.split_at_mut( shapes.len() / 2 );
+        let bound = shapes.iter().fold(Rect::empty(), |bound, shape| bound.union(shape.bounding_box()));
+        let centroids = shapes.iter().fold(Rect::empty(), |bound, shape| bound.grow(shape.position()));
+
+        let split = Bvh::find_split(shapes, bound, centroids);
+
+        let partitioned = match split {
+            // Testing every primitive of a small leaf beats traversing two more nodes
+            Some(( _, _, cost )) if shapes.len() <= MAX_LEAF_SIZE && cost >= shapes.len() as f32 => return Bvh::push_leaf(nodes, shapes, start),
+            Some(( axis, bin, _ )) => {
+                let middle = itertools::partition(shapes.iter_mut(), |shape| Self::bin(shape.position()[axis], centroids, axis) <= bin);
+
+                // Rounding can put every centroid on one side
+                (middle > 0 && middle < shapes.len()).then_some(middle)
+            },
+            None => None
+        };
+
+        let middle = match partitioned {
+            Some(middle) => middle,
+            None if shapes.len() <= MAX_LEAF_SIZE => return Bvh::push_leaf(nodes, shapes, start),
+            None => {
+                // Median along the axis over which the centroids spread the most
+                let size = centroids.max - centroids.min;
+                let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+
+                let middle = shapes.len() / 2;
+                shapes.select_nth_unstable_by(middle, |a, b| a.position()[axis].total_cmp(&b.position()[axis]));
 
-            Bvh::from_child(
-                Box::new(Bvh::construct(left, dim + 1)),
-                Box::new(Bvh::construct(right, dim + 1))
-            )
+                middle
+            }
+        };
+
+        let ( left, right ) = shapes.split_at_mut(middle);
+
+        Bvh::push_inner(nodes, left, right, start, |nodes, shapes, start| Bvh::build_node(nodes, shapes, start, depth + 1));
+    }
+
+    fn bin(centroid: f32, centroids: Rect, axis: usize) -> usize {
+        let relative = (centroid - centroids.min[axis]) / (centroids.max[axis] - centroids.min[axis]);
+
+        ((relative * BINS as f32) as usize).min(BINS - 1)
+    }
+
+    /// Cheapest split as its axis, the last bin on the left, and its cost relative to intersecting a primitive
+    fn find_split(shapes: &[T], bound: Rect, centroids: Rect) -> Option<( usize, usize, f32 )> {
+        let area = bound.surface_area();
+        let mut best: Option<( usize, usize, f32 )> = None;
+
+        for axis in 0..3 {
+            if centroids.max[axis] <= centroids.min[axis] { continue }
+
+            let mut bins = [( Rect::empty(), 0usize ); BINS];
+            for shape in shapes {
+                let ( bin_bound, count ) = &mut bins[Self::bin(shape.position()[axis], centroids, axis)];
+                *bin_bound = bin_bound.union(shape.bounding_box());
+                *count += 1;
+            }
+
+            // Area and count of everything right of each split, then swept from the left
+            let mut right = [( 0.0, 0 ); BINS];
+            let mut accumulated = ( Rect::empty(), 0 );
+            for i in (1..BINS).rev() {
+                accumulated = ( accumulated.0.union(bins[i].0), accumulated.1 + bins[i].1 );
+                right[i] = ( accumulated.0.surface_area(), accumulated.1 );
+            }
+
+            let mut left = ( Rect::empty(), 0 );
+            for i in 0..BINS - 1 {
+                left = ( left.0.union(bins[i].0), left.1 + bins[i].1 );
+
+                let ( right_area, right_count ) = right[i + 1];
+                if left.1 == 0 || right_count == 0 { continue }
+
+                let cost = TRAVERSAL_COST + (left.0.surface_area() * left.1 as f32 + right_area * right_count as f32) / area;
+                if cost.is_finite() && best.is_none_or(|( _, _, best )| cost < best) {
+                    best = Some(( axis, i, cost ));
+                }
+            }
         }
+
+        best
     }
 
+    /// Closest intersection along the ray
     pub fn intersects(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>> {
-        if !self.bound.intersects(ray) { return None; }
+        let scale = ray.dir.length();
+        let mut closest = None;
 
-        if let Some(shape) = self.shape {
-            shape.ray_intersection(ray)
-        }
-        else {
-            let left = self.lhs.as_ref().unwrap().intersects(ray);
-            let right = self.rhs.as_ref().unwrap().intersects(ray);
+        self.traverse(ray, f32::INFINITY, |shape, tmax| {
+            if let Some(inter) = shape.ray_intersection(ray) {
+                let t = inter.point.distance(ray.start) / scale;
 
-            if let Some(left) = left {
-                if let Some(right) = right {
-                    if left.point.distance_squared(ray.start) < right.point.distance_squared(ray.start) { Some(left) } else { Some(right) }
+                if t < *tmax {
+                    *tmax = t;
+                    closest = Some(inter);
                 }
-                else {
-                    Some(left)
+            }
+
+            false
+        });
+
+        closest
+    }
+
+    /// Whether anything lies along the ray closer than `distance` from its start, stopping at the first hit found
+    pub fn occluded(&self, ray: &Ray, distance: f32) -> bool {
+        let scale = ray.dir.length();
+
+        self.traverse(ray, distance, |shape, tmax| shape.occludes(ray, *tmax * scale))
+    }
+
+    /// Walks the nodes with an explicit stack, entering the nearer child first and skipping boxes beyond `tmax`
+    ///
+    /// `visit` tests a shape, lowering `tmax` to the distance of its hit, and returns true to end the walk, which is then returned.
+    /// Distances along the ray are in units of its direction, which scattered rays don't always normalize.
+    fn traverse<'s>(&'s self, ray: &Ray, distance: f32, mut visit: impl FnMut(&'s T, &mut f32) -> bool) -> bool {
+        let inv_dir = ray.dir.recip();
+        let mut tmax = distance / ray.dir.length();
+
+        // First, so that their hits cull the boxes behind them
+        for shape in &self.unbounded {
+            if visit(shape, &mut tmax) { return true }
+        }
+
+        let mut stack = [( 0u32, 0.0f32 ); MAX_DEPTH];
+        let mut len = 0;
+
+        let mut index = 0;
+        if self.nodes.first().and_then(|root| root.entry(ray.start, inv_dir, tmax)).is_none() { return false }
+
+        loop {
+            let node = &self.nodes[index];
+
+            if node.count > 0 {
+                let start = node.offset as usize;
+
+                for shape in &self.shapes[start..start + node.count as usize] {
+                    if visit(shape, &mut tmax) { return true }
                 }
             }
             else {
-                right
+                let ( left, right ) = ( index + 1, node.offset as usize );
+
+                match ( self.nodes[left].entry(ray.start, inv_dir, tmax), self.nodes[right].entry(ray.start, inv_dir, tmax) ) {
+                    ( Some(t_left), Some(t_right) ) => {
+                        let ( near, far, t_far ) = if t_left <= t_right { ( left, right, t_right ) } else { ( right, left, t_left ) };
+
+                        stack[len] = ( far as u32, t_far );
+                        len += 1;
+                        index = near;
+                        continue;
+                    },
+                    ( Some(_), None ) => { index = left; continue },
+                    ( None, Some(_) ) => { index = right; continue },
+                    ( None, None ) => {}
+                }
+            }
+
+            // Back to the farther child of the last split, unless a hit has been found in front of it since
+            loop {
+                if len == 0 { return false }
+                len -= 1;
+
+                let ( next, t ) = stack[len];
+                if t < tmax {
+                    index = next as usize;
+                    break;
+                }
             }
         }
     }
+
+    /// Bounds of the shapes in the hierarchy, empty when all of them are unbounded
+    pub fn bound(&self) -> Rect {
+        self.nodes.first().map_or(Rect::empty(), Node::bound)
+    }
+
+    /// Number of shapes, bounded or not
+    pub fn len(&self) -> usize {
+        self.shapes.len() + self.unbounded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every shape, those in the hierarchy first
+    pub fn shapes(&self) -> impl Iterator<Item = &T> {
+        self.shapes.iter().chain(&self.unbounded)
+    }
+
+    /// Shape at `index` among [`Bvh::shapes`]
+    pub fn shape(&self, index: usize) -> &T {
+        self.shapes.get(index).unwrap_or_else(|| &self.unbounded[index - self.shapes.len()])
+    }
+
+    /// Shapes kept out of the hierarchy for lack of finite bounds
+    pub fn unbounded(&self) -> &[T] {
+        &self.unbounded
+    }
+
+    /// Runs of shapes held by each leaf
+    pub fn leaves(&self) -> impl Iterator<Item = &[T]> {
+        self.nodes.iter()
+            .filter(|node| node.count > 0)
+            .map(|node| &self.shapes[node.offset as usize..(node.offset + node.count) as usize])
+    }
+
+    /// Length of the longest path from the root to a leaf
+    pub fn depth(&self) -> usize {
+        if self.nodes.is_empty() { 0 } else { self.depth_from(0) }
+    }
+
+    fn depth_from(&self, index: usize) -> usize {
+        let node = &self.nodes[index];
+        if node.count > 0 { return 1 }
+
+        1 + self.depth_from(index + 1).max(self.depth_from(node.offset as usize))
+    }
+
+    /// Expected cost of tracing a ray through the hierarchy according to the surface area heuristic, in primitive intersections
+    ///
+    /// Unbounded shapes are tested by every ray, and each count fully.
+    pub fn cost(&self) -> f32 {
+        let area = self.bound().surface_area();
+
+        self.unbounded.len() as f32 + self.nodes.iter().map(|node| {
+            let chance = node.bound().surface_area() / area;
+
+            if node.count > 0 { chance * node.count as f32 } else { chance * TRAVERSAL_COST }
+        }).sum::<f32>()
+    }
 }
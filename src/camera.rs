@@ -0,0 +1,282 @@
+use std::f32::consts::{TAU, PI, FRAC_PI_2};
+
+use glam::{Vec2, Vec3, Quat, Mat3};
+
+use crate::{shape::Ray, motion::Motion, material::tangent_to_world_matrix, rng::random};
+
+/// Shape of the lens opening, which gives its shape to out of focus highlights
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Aperture {
+    #[default]
+    Circle,
+    /// Regular polygon with `blades` sides, rotated by `rotation` radians
+    Polygon { blades: u32, rotation: f32 }
+}
+
+impl Aperture {
+    /// Uniformly samples a point in the aperture of radius 1
+    fn sample(self) -> Vec2 {
+        match self {
+            Aperture::Circle => {
+                let r = random::<f32>().sqrt();
+                let theta = random::<f32>() * TAU;
+
+                Vec2::new(r * theta.cos(), r * theta.sin())
+            },
+            Aperture::Polygon { blades, rotation } => {
+                let blades = blades.max(3);
+
+                // Pick one of the triangles going from the center to a side, then a point in it
+                let side = (random::<f32>() * blades as f32) as u32 % blades;
+                let angle = |i: u32| rotation + i as f32 / blades as f32 * TAU;
+                let a = Vec2::from_angle(angle(side));
+                let b = Vec2::from_angle(angle(side + 1));
+
+                let ( mut u, mut v ) = ( random::<f32>(), random::<f32>() );
+                if u + v > 1.0 {
+                    ( u, v ) = ( 1.0 - u, 1.0 - v );
+                }
+
+                a*u + b*v
+            }
+        }
+    }
+}
+
+/// How pixels are mapped to ray directions
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// Parallel rays, `height` is the size of the view in world units
+    Orthographic { height: f32 },
+    /// 360° panorama, longitude along the width and latitude along the height
+    Equirectangular,
+    /// Equidistant fisheye, `fov` is the angle in radians covered by the height of the image
+    Fisheye { fov: f32 }
+}
+
+/// Angle covered by the perspective projection, in radians
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldOfView {
+    /// Angle between the top and bottom edges of the image
+    Vertical(f32),
+    /// Angle between the left and right edges of the image
+    Horizontal(f32)
+}
+
+impl FieldOfView {
+    /// Horizontal field of view of a lens, with the sensor width spanning the width of the image like the horizontal sensor fit of DCC tools
+    ///
+    /// Both lengths are in millimeters.
+    pub fn from_focal_length(focal_length: f32, sensor_width: f32) -> Self {
+        FieldOfView::Horizontal(2.0 * (sensor_width / (2.0 * focal_length)).atan())
+    }
+
+    /// Half size of the image plane at distance 1, as `(width, height)`
+    fn half_extent(self, aspect_ratio: f32) -> Vec2 {
+        match self {
+            FieldOfView::Vertical(fov) => {
+                let h = (fov / 2.0).tan();
+                Vec2::new(h * aspect_ratio, h)
+            },
+            FieldOfView::Horizontal(fov) => {
+                let w = (fov / 2.0).tan();
+                Vec2::new(w, w / aspect_ratio)
+            }
+        }
+    }
+}
+
+/// Placement of one eye of a stereo pair, see [`StereoRig`](crate::stereo::StereoRig)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Eye {
+    /// Signed distance of the eye to the right of the camera
+    pub offset: f32,
+    /// Distance at which both eyes see the same point, infinite for parallel views
+    pub convergence: f32
+}
+
+/// Width in millimeters of a 35mm full frame sensor, the default of most DCC tools
+pub const FULL_FRAME_SENSOR_WIDTH: f32 = 36.0;
+
+/// Thin lens camera, which behaves as a pinhole while `aperture` is zero
+///
+/// The lens only applies to the perspective projection.
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub position: Vec3,
+    pub orientation: Quat,
+    pub projection: Projection,
+    pub fov: FieldOfView,
+    /// Radius of the lens
+    pub aperture: f32,
+    pub aperture_shape: Aperture,
+    /// Distance from the camera to the plane in focus, along the view direction
+    pub focus_distance: f32,
+    /// Interval over which ray times are sampled, as `(open, close)`, relative to the time of the frame
+    pub shutter: (f32, f32),
+    /// Overrides `position` and `orientation` over time, scale is ignored
+    pub animation: Option<Motion>,
+    /// Offsets the camera to one side of a stereo pair
+    pub eye: Option<Eye>
+}
+
+impl Camera {
+    pub fn new(position: Vec3, orientation: Quat) -> Self {
+        Camera {
+            position,
+            orientation,
+            projection: Projection::Perspective,
+            fov: FieldOfView::Vertical(90.0_f32.to_radians()),
+            aperture: 0.0,
+            aperture_shape: Aperture::Circle,
+            focus_distance: 1.0,
+            shutter: ( 0.0, 0.0 ),
+            animation: None,
+            eye: None
+        }
+    }
+
+    /// Places a camera at `eye` looking towards `target`, with `up` pointing to the top of the image
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let forward = (target - eye).normalize();
+        // The camera looks down +Z with +X to the right of the image, so the basis is left-handed
+        let right = up.cross(forward).normalize_or_zero();
+        // Looking along `up` leaves the roll free, any axis perpendicular to the view will do
+        let right = if right == Vec3::ZERO { tangent_to_world_matrix(forward).x_axis } else { right };
+        let up = forward.cross(right);
+
+        Camera::new(eye, Quat::from_mat3(&Mat3::from_cols(right, up, forward)))
+    }
+
+    pub fn set_fov(mut self, fov: FieldOfView) -> Self {
+        self.fov = fov;
+        self
+    }
+    /// Sets the field of view from the focal length of a lens and the width of the sensor behind it, both in millimeters
+    pub fn set_focal_length(mut self, focal_length: f32, sensor_width: f32) -> Self {
+        self.fov = FieldOfView::from_focal_length(focal_length, sensor_width);
+        self
+    }
+    pub fn set_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+    pub fn set_aperture(mut self, aperture: f32) -> Self {
+        self.aperture = aperture;
+        self
+    }
+    pub fn set_aperture_shape(mut self, shape: Aperture) -> Self {
+        self.aperture_shape = shape;
+        self
+    }
+    pub fn set_focus_distance(mut self, distance: f32) -> Self {
+        self.focus_distance = distance;
+        self
+    }
+    pub fn set_shutter(mut self, open: f32, close: f32) -> Self {
+        self.shutter = ( open, close );
+        self
+    }
+    pub fn set_animation(mut self, animation: Motion) -> Self {
+        self.animation = Some(animation);
+        self
+    }
+    pub fn set_eye(mut self, eye: Option<Eye>) -> Self {
+        self.eye = eye;
+        self
+    }
+
+    /// Position and orientation at a given time
+    pub fn placement(&self, time: f32) -> ( Vec3, Quat ) {
+        match &self.animation {
+            Some(animation) => {
+                let transform = animation.at(time);
+                ( transform.translation, transform.rotation )
+            },
+            None => ( self.position, self.orientation )
+        }
+    }
+}
+
+/// Returns the ray passing through a pixel given its position
+///
+/// With a non-zero aperture, the ray starts at a random point of the lens and goes through the point in focus.
+/// The time of the ray is picked at random while the shutter is open, starting from the time of the frame.
+///
+/// An [`Eye`] moves the camera sideways and shifts the image plane so that both views line up at the convergence distance.
+/// Equirectangular views instead start each ray on a circle, tangent to its direction, for omnidirectional stereo.
+///
+/// Fisheye views have no ray for the pixels outside of their image circle, which stay black.
+pub fn pixel_as_ray(width: u32, height: u32, camera: &Camera, x: f32, y: f32, frame_time: f32) -> Option<Ray> {
+    let pos = Vec2::new(x, y);
+
+    let canvas_size = Vec2::new(width as f32, height as f32);
+
+    let normalized_coordinates = pos / canvas_size * 2.0 - Vec2::ONE; // Range -1..1
+
+    let aspect_ratio = canvas_size.x / canvas_size.y;
+
+    // Camera space coordinates with y going up, -aspect_ratio..aspect_ratio horizontally and -1..1 vertically
+    let screen = Vec2::new(normalized_coordinates.x * aspect_ratio, -normalized_coordinates.y);
+
+    let ( open, close ) = camera.shutter;
+    let time = frame_time + if close > open { open + (close - open) * random::<f32>() } else { open };
+
+    let ( position, orientation ) = camera.placement(time);
+
+    let local_ray = |start: Vec3, dir: Vec3| Some(Ray {
+        start: position + orientation.mul_vec3(start),
+        dir: orientation.mul_vec3(dir.normalize()),
+        time
+    });
+
+    let Eye { offset, convergence } = camera.eye.unwrap_or(Eye { offset: 0.0, convergence: f32::INFINITY });
+    let eye = Vec3::X * offset;
+
+    match camera.projection {
+        Projection::Perspective => {},
+        Projection::Orthographic { height } => {
+            return local_ray(eye + (screen * height / 2.0).extend(0.0), Vec3::Z);
+        },
+        Projection::Equirectangular => {
+            let longitude = normalized_coordinates.x * PI;
+            let latitude = -normalized_coordinates.y * FRAC_PI_2;
+
+            let dir = Vec3::new(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos());
+
+            // Each eye turns on a horizontal circle, to the side of the looking direction
+            let start = Vec3::new(longitude.cos(), 0.0, -longitude.sin()) * offset;
+            let dir = if convergence.is_finite() { dir * convergence - start } else { dir };
+
+            return local_ray(start, dir);
+        },
+        Projection::Fisheye { fov } => {
+            // The angle to the view direction grows linearly with the distance to the center of the image
+            let distance = screen.length();
+            if distance > 1.0 {
+                return None;
+            }
+
+            let theta = distance * fov / 2.0;
+            let phi = screen.y.atan2(screen.x);
+
+            let dir = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+            return local_ray(eye, dir);
+        }
+    }
+
+    let mut ray_dir = (normalized_coordinates * Vec2::new(1.0, -1.0) * camera.fov.half_extent(aspect_ratio)).extend(1.0);
+    // Off-axis shift, so that the center of the image looks at the convergence point instead of toeing in
+    ray_dir.x -= offset / convergence;
+
+    if camera.aperture <= 0.0 {
+        return local_ray(eye, ray_dir);
+    }
+
+    let focus = ray_dir * camera.focus_distance;
+    let lens = (camera.aperture_shape.sample() * camera.aperture).extend(0.0);
+
+    local_ray(eye + lens, focus - lens)
+}
@@ -0,0 +1,51 @@
+//! Sampling of discrete distributions
+
+/// Piecewise constant distribution over `0..1`, proportional to a list of values
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    /// Cumulated values, normalized so that the last one is 1
+    cdf: Vec<f32>,
+    /// Sum of the values
+    total: f32
+}
+
+impl Distribution {
+    pub fn new(values: impl Iterator<Item = f32>) -> Self {
+        let mut total = 0.0;
+        let mut cdf: Vec<f32> = values.map(|v| { total += v; total }).collect();
+
+        if total > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= total);
+        }
+        else {
+            // Uniform when all the values are zero
+            let len = cdf.len() as f32;
+            cdf.iter_mut().enumerate().for_each(|(i, c)| *c = (i + 1) as f32 / len);
+        }
+
+        Distribution { cdf, total }
+    }
+
+    /// Picks a bucket, returning its index and the density of the continuous distribution in it
+    pub fn sample(&self, u: f32) -> ( usize, f32 ) {
+        let i = self.cdf.partition_point(|&c| c <= u).min(self.cdf.len() - 1);
+
+        ( i, self.pdf(i) )
+    }
+
+    /// Density of the continuous distribution in a bucket
+    pub fn pdf(&self, i: usize) -> f32 {
+        self.probability(i) * self.cdf.len() as f32
+    }
+
+    /// Chances of picking a bucket
+    pub fn probability(&self, i: usize) -> f32 {
+        let previous = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+
+        self.cdf[i] - previous
+    }
+
+    pub fn total(&self) -> f32 {
+        self.total
+    }
+}
@@ -0,0 +1,141 @@
+//! Environment maps, lighting the scene from a panoramic high dynamic range image
+
+use std::{f32::consts::PI, fs::File, io::BufReader, path::Path};
+
+use glam::{Vec2, Vec3, Quat};
+use image::{Rgb32FImage, ImageError, codecs::hdr::HdrDecoder, ImageFormat, error::DecodingError};
+
+use crate::{material::Color, distribution::Distribution, rng::random};
+
+/// Equirectangular panorama surrounding the scene, seen in every direction nothing else is
+///
+/// The center of the image is towards +Z and the top towards +Y, as rendered by equirectangular cameras.
+/// Directions are sampled in proportion to the luminance of the pixels, so that small bright features like the sun
+/// are found by shadow rays rather than by chance.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    data: Rgb32FImage,
+    /// Angle in radians around +Y by which the panorama is turned
+    rotation: f32,
+    /// Multiplies the radiance of the image
+    intensity: f32,
+    /// Distribution of the rows, then of the pixels within each row
+    rows: Distribution,
+    columns: Vec<Distribution>
+}
+
+impl EnvironmentMap {
+    pub fn new(data: Rgb32FImage) -> Self {
+        let ( width, height ) = data.dimensions();
+
+        // Rows near the poles cover less of the sphere, and are weighted by their solid angle
+        let columns: Vec<Distribution> = (0..height).map(|y| {
+            let sin_theta = ((y as f32 + 0.5) / height as f32 * PI).sin();
+
+            Distribution::new((0..width).map(|x| luminance(data.get_pixel(x, y).into()) * sin_theta))
+        }).collect();
+
+        let rows = Distribution::new(columns.iter().map(Distribution::total));
+
+        EnvironmentMap { data, rotation: 0.0, intensity: 1.0, rows, columns }
+    }
+
+    /// Loads a Radiance HDR or OpenEXR file, or any other image though without its dynamic range
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+
+        // Opened as a generic image, Radiance files get squeezed into 8 bits
+        let is_hdr = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        if !is_hdr {
+            return Ok(EnvironmentMap::new(image::open(path)?.into_rgb32f()));
+        }
+
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path).map_err(ImageError::IoError)?))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+
+        let data = Rgb32FImage::from_raw(metadata.width, metadata.height, pixels.into_iter().flat_map(|p| p.0).collect())
+            .ok_or_else(|| ImageError::Decoding(DecodingError::new(ImageFormat::Hdr.into(), "pixel count does not match the size")))?;
+
+        Ok(EnvironmentMap::new(data))
+    }
+
+    pub fn set_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+    pub fn set_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn rotation(&self) -> f32 { self.rotation }
+    pub fn intensity(&self) -> f32 { self.intensity }
+    pub fn dimensions(&self) -> ( u32, u32 ) { self.data.dimensions() }
+
+    /// Position in the image of a direction, from 0 to 1 with y going down
+    fn image_position(&self, dir: Vec3) -> Vec2 {
+        let dir = Quat::from_rotation_y(-self.rotation) * dir;
+
+        let longitude = dir.x.atan2(dir.z);
+        // Rather than the arcsine of y, which loses precision towards the poles
+        let latitude = dir.y.atan2(Vec2::new(dir.x, dir.z).length());
+
+        Vec2::new(longitude / (2.0*PI) + 0.5, 0.5 - latitude / PI)
+    }
+
+    fn direction(&self, uv: Vec2) -> Vec3 {
+        let longitude = (uv.x - 0.5) * 2.0*PI;
+        let latitude = (0.5 - uv.y) * PI;
+
+        Quat::from_rotation_y(self.rotation) * Vec3::new(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos())
+    }
+
+    fn pixel(&self, uv: Vec2) -> ( u32, u32 ) {
+        let ( width, height ) = self.data.dimensions();
+
+        (
+            ((uv.x * width as f32) as u32).min(width - 1),
+            ((uv.y * height as f32) as u32).min(height - 1)
+        )
+    }
+
+    /// Radiance coming from a direction, constant over each pixel
+    pub fn radiance(&self, dir: Vec3) -> Color {
+        let ( x, y ) = self.pixel(self.image_position(dir));
+
+        Color::from(self.data.get_pixel(x, y)) * self.intensity
+    }
+
+    /// Samples a direction in proportion to the luminance it brings, returning it with its density over solid angle
+    pub fn sample(&self) -> ( Vec3, f32 ) {
+        let ( y, row_pdf ) = self.rows.sample(random());
+        let ( x, column_pdf ) = self.columns[y].sample(random());
+
+        let ( width, height ) = self.data.dimensions();
+        let uv = Vec2::new((x as f32 + random::<f32>()) / width as f32, (y as f32 + random::<f32>()) / height as f32);
+
+        let dir = self.direction(uv);
+
+        ( dir, row_pdf * column_pdf / EnvironmentMap::jacobian(uv) )
+    }
+
+    /// Density of [`EnvironmentMap::sample`] over solid angle
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        let uv = self.image_position(dir);
+        let ( x, y ) = self.pixel(uv);
+
+        self.rows.pdf(y as usize) * self.columns[y as usize].pdf(x as usize) / EnvironmentMap::jacobian(uv)
+    }
+
+    /// Solid angle covered by a unit of area of the image, around a point of it
+    fn jacobian(uv: Vec2) -> f32 {
+        let theta = uv.y * PI;
+
+        (2.0*PI * PI * theta.sin()).max(f32::EPSILON)
+    }
+}
+
+fn luminance(color: Color) -> f32 {
+    0.2126*color.r + 0.7152*color.g + 0.0722*color.b
+}
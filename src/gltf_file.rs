@@ -0,0 +1,250 @@
+//! glTF 2.0 import (`.gltf` with external or embedded buffers, and binary `.glb`)
+//!
+//! glTF is right-handed while the renderer looks down +Z with +X to the right of the image,
+//! so the X axis is mirrored on import to keep images from being flipped.
+
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use glam::{Vec2, Vec3, Mat3, Mat4, Quat};
+use gltf::{camera::Projection, image::Format, mesh::Mode, material::AlphaMode, texture::WrappingMode};
+use image::{RgbImage, Rgb};
+
+use crate::{
+    camera::{Camera, FieldOfView},
+    material::{Material, Color},
+    mesh::MeshError,
+    shape::{Triangle, Vertex},
+    texture::{Texture, TextureWrapping}
+};
+
+/// A camera found in the node hierarchy
+#[derive(Debug)]
+pub struct GltfCamera {
+    pub name: Option<String>,
+    pub camera: Camera
+}
+
+/// Every triangle and camera of the default scene of a glTF file, in world space
+#[derive(Debug, Default)]
+pub struct GltfScene {
+    pub triangles: Vec<Triangle>,
+    pub cameras: Vec<GltfCamera>
+}
+
+/// Mirrors the X axis to go from glTF's right-handed space to the renderer's
+const MIRROR: Mat4 = Mat4::from_cols_array(&[
+    -1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0
+]);
+
+fn convert_image(data: &gltf::image::Data, path: &Path) -> Result<RgbImage, MeshError> {
+    let ( width, height ) = ( data.width, data.height );
+    let pixels = &data.pixels;
+
+    if width == 0 || height == 0 {
+        return Err(MeshError::Invalid { path: path.to_owned(), message: format!("image of {}x{} pixels", width, height) });
+    }
+
+    // Reads channel `c` of pixel `i` as a value in 0..1
+    let channel: Box<dyn Fn(usize, usize) -> f32> = match data.format {
+        Format::R8 | Format::R8G8 | Format::R8G8B8 | Format::R8G8B8A8 => {
+            let n = pixels.len() / (width * height) as usize;
+            Box::new(move |i, c| pixels[i*n + c.min(n - 1)] as f32 / 255.0)
+        },
+        Format::R16 | Format::R16G16 | Format::R16G16B16 | Format::R16G16B16A16 => {
+            let n = pixels.len() / (width * height) as usize / 2;
+            Box::new(move |i, c| {
+                let o = (i*n + c.min(n - 1)) * 2;
+                u16::from_le_bytes([ pixels[o], pixels[o + 1] ]) as f32 / 65535.0
+            })
+        },
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => {
+            let n = pixels.len() / (width * height) as usize / 4;
+            Box::new(move |i, c| {
+                let o = (i*n + c) * 4;
+                f32::from_le_bytes(pixels[o..o + 4].try_into().unwrap()).clamp(0.0, 1.0)
+            })
+        }
+    };
+
+    Ok(RgbImage::from_fn(width, height, |x, y| {
+        let i = (y * width + x) as usize;
+        Rgb([0, 1, 2].map(|c| (channel(i, c) * 255.0).round() as u8))
+    }))
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    textures: HashMap<usize, Arc<Texture>>,
+    materials: HashMap<Option<usize>, Material>,
+    scene: GltfScene
+}
+
+impl Importer<'_> {
+    fn texture(&mut self, texture: gltf::Texture) -> Result<Arc<Texture>, MeshError> {
+        if let Some(t) = self.textures.get(&texture.index()) {
+            return Ok(t.clone());
+        }
+
+        let wrapping = match texture.sampler().wrap_s() {
+            WrappingMode::Repeat => TextureWrapping::Repeat,
+            WrappingMode::MirroredRepeat => TextureWrapping::MirroredRepeat,
+            WrappingMode::ClampToEdge => TextureWrapping::ClampToEdge
+        };
+
+        let converted = Arc::new(Texture::new(convert_image(&self.images[texture.source().index()], self.path)?).set_wrapping(wrapping));
+
+        self.textures.insert(texture.index(), converted.clone());
+        Ok(converted)
+    }
+
+    /// Maps a metallic-roughness material to the closest material kind
+    fn material(&mut self, material: gltf::Material) -> Result<Material, MeshError> {
+        if let Some(m) = self.materials.get(&material.index()) {
+            return Ok(m.clone());
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, alpha] = pbr.base_color_factor();
+        let base = Color::new(r, g, b);
+
+        let emissive = Color::from(Vec3::from(material.emissive_factor())) * material.emissive_strength().unwrap_or(1.0);
+        let emission = emissive.r.max(emissive.g).max(emissive.b);
+
+        let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+
+        let mut converted = if emission > 0.0 {
+            Material::new_emmitive(emissive / emission, emission)
+        }
+        else if transmission > 0.5 || (material.alpha_mode() == AlphaMode::Blend && alpha < 0.5) {
+            Material::new_transparent(material.ior().unwrap_or(1.5))
+        }
+        else if pbr.metallic_factor() >= 0.5 {
+            Material::new_rough_metal(base, pbr.roughness_factor())
+        }
+        else {
+            Material::new_lambertian(base)
+        };
+
+        // Emissive materials are lit by their emission texture, and only on the front unless double sided
+        let texture = if emission > 0.0 { material.emissive_texture() } else { pbr.base_color_texture() };
+        if let Some(info) = texture {
+            converted = converted.set_texture(self.texture(info.texture())?);
+        }
+        converted = converted.set_two_sided(material.double_sided());
+        if let Some(normal) = material.normal_texture() {
+            converted = converted.set_normal(self.texture(normal.texture())?);
+        }
+
+        self.materials.insert(material.index(), converted.clone());
+        Ok(converted)
+    }
+
+    fn primitive(&mut self, primitive: gltf::Primitive, transform: Mat4) -> Result<(), MeshError> {
+        let buffers = self.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let Some(positions) = reader.read_positions() else { return Ok(()) };
+        let positions: Vec<Vec3> = positions.map(Vec3::from).collect();
+        let normals: Option<Vec<Vec3>> = reader.read_normals().map(|n| n.map(Vec3::from).collect());
+        // glTF puts the origin of texture coordinates at the top left
+        let tex_coords: Option<Vec<Vec2>> = reader.read_tex_coords(0)
+            .map(|t| t.into_f32().map(|[u, v]| Vec2::new(u, 1.0 - v)).collect());
+
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect()
+        };
+
+        let faces: Vec<[u32; 3]> = match primitive.mode() {
+            Mode::Triangles => indices.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+            Mode::TriangleStrip => indices.windows(3).enumerate()
+                .map(|(i, w)| if i % 2 == 0 { [w[0], w[1], w[2]] } else { [w[1], w[0], w[2]] })
+                .collect(),
+            Mode::TriangleFan => indices.windows(2).skip(1).map(|w| [indices[0], w[0], w[1]]).collect(),
+            _ => return Ok(()) // Points and lines
+        };
+
+        let material = self.material(primitive.material())?;
+
+        for face in faces {
+            if face.iter().any(|&i| i as usize >= positions.len()) { continue }
+
+            let [a, b, c] = face.map(|i| positions[i as usize]);
+            let face_normal = (b - a).cross(c - a).normalize_or_zero();
+
+            let vertex = |i: u32| Vertex {
+                pos: positions[i as usize],
+                normal: normals.as_ref().map_or(face_normal, |n| n[i as usize]),
+                tex: tex_coords.as_ref().map_or(Vec2::ZERO, |t| t[i as usize])
+            };
+
+            self.scene.triangles.push(
+                Triangle::new(vertex(face[0]), vertex(face[1]), vertex(face[2]), material.clone()).transform(transform)
+            );
+        }
+
+        Ok(())
+    }
+
+    fn node(&mut self, node: gltf::Node, parent: Mat4) -> Result<(), MeshError> {
+        let local = Mat4::from_cols_array_2d(&node.transform().matrix());
+        let transform = parent * local;
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(primitive, MIRROR * transform)?;
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            if let Projection::Perspective(perspective) = camera.projection() {
+                let ( _, rotation, translation ) = transform.to_scale_rotation_translation();
+
+                // glTF cameras look down -Z, flipping Z on top of the mirrored X keeps a proper rotation
+                let orientation = Mat3::from_mat4(MIRROR) * Mat3::from_quat(rotation) * Mat3::from_diagonal(Vec3::new(1.0, 1.0, -1.0));
+
+                self.scene.cameras.push(GltfCamera {
+                    name: camera.name().map(str::to_owned),
+                    camera: Camera::new(MIRROR.transform_point3(translation), Quat::from_mat3(&orientation).normalize())
+                        .set_fov(FieldOfView::Vertical(perspective.yfov()))
+                });
+            }
+        }
+
+        for child in node.children() {
+            self.node(child, transform)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads the default scene of a glTF file, or its first scene if none is marked as default
+pub fn load_gltf_file<P: AsRef<Path>>(file: P) -> Result<GltfScene, MeshError> {
+    let path = file.as_ref();
+
+    let ( document, buffers, images ) = gltf::import(path)
+        .map_err(|e| MeshError::Invalid { path: path.to_owned(), message: e.to_string() })?;
+
+    let mut importer = Importer {
+        path,
+        buffers: &buffers,
+        images: &images,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        scene: GltfScene::default()
+    };
+
+    if let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) {
+        for node in scene.nodes() {
+            importer.node(node, Mat4::IDENTITY)?;
+        }
+    }
+
+    Ok(importer.scene)
+}
@@ -0,0 +1,117 @@
+//! Meshes placed in scenes by reference, forming the top level of a two-level acceleration structure
+
+use std::sync::Arc;
+
+use glam::{Vec3, Mat4};
+
+use crate::{shape::*, mesh::Mesh, material::Material, intersection::{Inter, Traceable, transform_pdf}, rng::random};
+
+/// A mesh placed in the scene by a transform, sharing its triangles and their hierarchy with the other instances
+///
+/// Rays are brought into the space of the mesh rather than the mesh into the scene,
+/// so thousands of instances take little more memory than one.
+/// Emissive triangles are sampled through their instance, which intersections report as their `parent`.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    mesh: Arc<Mesh>,
+    to_world: Mat4,
+    to_local: Mat4,
+    bound: Rect,
+    /// Power of the emissive triangles, as placed by the instance
+    power: f32
+}
+
+impl Instance {
+    pub fn new(mesh: Arc<Mesh>, transform: Mat4) -> Self {
+        // Meshes without triangles have no bounds to transform
+        let local = mesh.bvh().bound();
+        let bound = if local.is_finite() { local.transform(transform) } else { Rect::infinite() };
+
+        let to_local = transform.inverse();
+        let power = mesh.power(transform, to_local);
+
+        Instance { mesh, to_world: transform, to_local, bound, power }
+    }
+
+    pub fn mesh(&self) -> &Arc<Mesh> {
+        &self.mesh
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        self.to_world
+    }
+
+    /// The ray in the space of the mesh with a normalized direction, and how much distances along it shrink or grow in that space
+    fn local_ray(&self, ray: &Ray) -> ( Ray, f32 ) {
+        let dir = self.to_local.transform_vector3(ray.dir);
+        let length = dir.length();
+
+        let local = Ray {
+            start: self.to_local.transform_point3(ray.start),
+            dir: dir / length,
+            time: ray.time
+        };
+
+        ( local, length / ray.dir.length() )
+    }
+}
+
+impl Shape for Instance {
+    fn position(&self) -> Vec3 {
+        let local = self.mesh.bvh().bound();
+
+        self.to_world.transform_point3((local.min + local.max) / 2.0)
+    }
+
+    fn bounding_box(&self) -> Rect {
+        self.bound
+    }
+}
+
+impl Traceable for Instance {
+    /// Triangles have materials of their own
+    fn material(&self) -> Option<&Material> {
+        None
+    }
+
+    fn ray_intersection(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>> {
+        let ( local, _ ) = self.local_ray(ray);
+
+        let inter = self.mesh.bvh().intersects(&local)?;
+
+        Some(Inter { parent: Some(self), ..inter.transform(self.to_world, self.to_local) })
+    }
+
+    fn power(&self) -> f32 {
+        self.power
+    }
+
+    /// Picks an emissive triangle in proportion to its power in the mesh, and samples it
+    fn sample_towards(&self, point: Vec3, time: f32) -> Option<( Inter<&dyn Traceable>, f32 )> {
+        let ( triangle, probability ) = self.mesh.sample_emissive(random())?;
+
+        let from = self.to_local.transform_point3(point);
+        let ( local, pdf ) = triangle.sample_towards(from, time)?;
+        let world = Inter { parent: Some(self as &dyn Traceable), ..local.clone().transform(self.to_world, self.to_local) };
+
+        let pdf = transform_pdf(pdf, from, &local, point, &world, self.to_world, self.to_local) * probability;
+        (pdf > 0.0).then_some(( world, pdf ))
+    }
+
+    fn pdf_towards(&self, point: Vec3, time: f32, inter: &Inter<&dyn Traceable>) -> f32 {
+        let probability = self.mesh.emissive_probability(inter.shape);
+        if probability == 0.0 { return 0.0 }
+
+        let from = self.to_local.transform_point3(point);
+        let local = inter.clone().transform(self.to_local, self.to_world);
+        let pdf = inter.shape.pdf_towards(from, time, &local);
+
+        transform_pdf(pdf, from, &local, point, inter, self.to_world, self.to_local) * probability
+    }
+
+    fn occludes(&self, ray: &Ray, distance: f32) -> bool {
+        let ( local, scale ) = self.local_ray(ray);
+
+        self.mesh.bvh().occluded(&local, distance * scale)
+    }
+}
@@ -1,9 +1,9 @@
 use std::f32::consts::PI;
 
-use glam::{Vec3, Vec2};
+use glam::{Vec3, Vec2, Mat4};
 use num::Zero;
 
-use crate::{shape::*, material::Material};
+use crate::{shape::*, material::{Material, MaterialKind, Color}, light::{cone_height, random_in_cone}, rng::random};
 
 pub trait Intersection<T> where
     T: ?Sized,
@@ -55,7 +55,7 @@ impl Intersection<Ray> for Rect {
     }
 }
 
-impl Intersection<Ray> for Sphere<'_> {
+impl Intersection<Ray> for Sphere {
     fn intersects(&self, ray: &Ray) -> bool {
         let to_center = self.pos - ray.start;
         let closest = to_center.project_onto(ray.dir);
@@ -64,7 +64,7 @@ impl Intersection<Ray> for Sphere<'_> {
     }
 }
 
-impl Intersection<Sphere<'_>> for Sphere<'_> {
+impl Intersection<Sphere> for Sphere {
     fn intersects(&self, other: &Sphere) -> bool {
         self.pos.distance_squared(other.pos) <= (self.radius+other.radius)*(self.radius+other.radius)
     }
@@ -73,25 +73,159 @@ impl Intersection<Sphere<'_>> for Sphere<'_> {
 #[derive(Debug, Clone)]
 pub struct Inter<T> {
     pub point: Vec3,
+    /// Shading normal, possibly interpolated, facing the incoming ray
     pub normal: Vec3,
+    /// Normal of the actual surface, on the same side as `normal`
+    pub geometric_normal: Vec3,
     pub front: bool,
-    pub shape: T
+    /// Point in the space of `shape`, where textures and colors are looked up
+    pub local_point: Vec3,
+    pub shape: T,
+    /// Outermost shape, such as an instance, in which `shape` was found, `None` when it stands on its own in the scene
+    pub parent: Option<T>
+}
+
+impl<T> Inter<T> {
+    /// Moves the intersection by `matrix`, `inverse` being its inverse
+    pub fn transform(self, matrix: Mat4, inverse: Mat4) -> Self {
+        // Normals are transformed by the inverse transpose to stay perpendicular under non-uniform scaling
+        let normal_mat = inverse.transpose();
+
+        Inter {
+            point: matrix.transform_point3(self.point),
+            normal: normal_mat.transform_vector3(self.normal).normalize(),
+            geometric_normal: normal_mat.transform_vector3(self.geometric_normal).normalize(),
+            ..self
+        }
+    }
+}
+
+/// Converts the density over solid angle of `local`, seen from `from` in the space of a shape, into the density of `world`, the same point moved by `to_world` and seen from `to`
+///
+/// Affine transforms stretch areas evenly around a point of a surface, so going through densities over area is exact.
+pub(crate) fn transform_pdf<T>(pdf: f32, from: Vec3, local: &Inter<T>, to: Vec3, world: &Inter<T>, to_world: Mat4, to_local: Mat4) -> f32 {
+    let area_per_solid_angle = |point: Vec3, inter: &Inter<T>| {
+        let to_inter = inter.point - point;
+        to_inter.length_squared() / to_inter.normalize().dot(inter.geometric_normal).abs()
+    };
+
+    let stretch = area_stretch(to_world, to_local, local.geometric_normal);
+    let pdf = pdf * area_per_solid_angle(to, world) / (area_per_solid_angle(from, local) * stretch);
+
+    if pdf.is_finite() { pdf } else { 0.0 }
+}
+
+/// How much `to_world` grows areas around a point of a surface with the unit `normal`
+pub(crate) fn area_stretch(to_world: Mat4, to_local: Mat4, normal: Vec3) -> f32 {
+    to_world.determinant().abs() * to_local.transpose().transform_vector3(normal).length()
+}
+
+/// Identifies a shape by where it lives, its vtable being left out
+pub(crate) fn address(shape: &dyn Traceable) -> usize {
+    shape as *const dyn Traceable as *const () as usize
 }
 
 pub trait Traceable
 where Self: Shape + std::marker::Sync {
-    fn material(&self) -> &Material;
+    /// Material of the surface, `None` for aggregates such as instances, whose intersections report the shape they found within them
+    fn material(&self) -> Option<&Material>;
     fn ray_intersection(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>>;
 
     /// Returns a texture coordinate according to a point on itself
     fn sample(&self, _p: Vec3) -> Vec2 {
         Vec2::ZERO
     }
+
+    /// Returns the color multiplying the albedo at a point on itself
+    fn color(&self, _p: Vec3) -> Color {
+        Color::WHITE
+    }
+
+    /// Area of the surface, zero where it isn't known
+    fn area(&self) -> f32 {
+        0.0
+    }
+
+    /// Power emitted by the surface, which guides the sampling of emissive shapes, zero for shapes that can't be sampled
+    fn power(&self) -> f32 {
+        // Textures are left out, as they would only refine the guess
+        match self.material().map(|material| &material.kind) {
+            Some(&MaterialKind::Emmitive { color, intensity, two_sided }) => {
+                let sides = if two_sided { 2.0 } else { 1.0 };
+                self.area() * (color.r + color.g + color.b) * intensity * sides
+            },
+            _ => 0.0
+        }
+    }
+
+    /// Samples a point of the surface seen from `point` at `time`, returning it with the density of its direction over solid angle
+    fn sample_towards(&self, _point: Vec3, _time: f32) -> Option<( Inter<&dyn Traceable>, f32 )> {
+        None
+    }
+
+    /// Density with which [`Traceable::sample_towards`] finds the point of an intersection from `point` at `time`
+    fn pdf_towards(&self, _point: Vec3, _time: f32, _inter: &Inter<&dyn Traceable>) -> f32 {
+        0.0
+    }
+
+    /// Whether the shape lies along the ray closer than `distance` from its start, for shadow rays that need any hit rather than the closest
+    fn occludes(&self, ray: &Ray, distance: f32) -> bool {
+        self.ray_intersection(ray).is_some_and(|inter| inter.point.distance(ray.start) < distance)
+    }
 }
 
-impl<'a> Traceable for Sphere<'a> {
-    fn material(&self) -> &Material {
-        &self.material
+/// Lets a BVH be built over borrowed shapes
+impl Shape for &dyn Traceable {
+    fn position(&self) -> Vec3 {
+        (**self).position()
+    }
+
+    fn bounding_box(&self) -> Rect {
+        (**self).bounding_box()
+    }
+}
+
+impl Traceable for &dyn Traceable {
+    fn material(&self) -> Option<&Material> {
+        (**self).material()
+    }
+
+    fn ray_intersection(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>> {
+        (**self).ray_intersection(ray)
+    }
+
+    fn sample(&self, p: Vec3) -> Vec2 {
+        (**self).sample(p)
+    }
+
+    fn color(&self, p: Vec3) -> Color {
+        (**self).color(p)
+    }
+
+    fn area(&self) -> f32 {
+        (**self).area()
+    }
+
+    fn power(&self) -> f32 {
+        (**self).power()
+    }
+
+    fn sample_towards(&self, point: Vec3, time: f32) -> Option<( Inter<&dyn Traceable>, f32 )> {
+        (**self).sample_towards(point, time)
+    }
+
+    fn pdf_towards(&self, point: Vec3, time: f32, inter: &Inter<&dyn Traceable>) -> f32 {
+        (**self).pdf_towards(point, time, inter)
+    }
+
+    fn occludes(&self, ray: &Ray, distance: f32) -> bool {
+        (**self).occludes(ray, distance)
+    }
+}
+
+impl Traceable for Sphere {
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
     }
 
     fn ray_intersection(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>> {
@@ -132,8 +266,11 @@ impl<'a> Traceable for Sphere<'a> {
         Some(Inter {
             point,
             normal,
+            geometric_normal: normal,
             front,
-            shape: self
+            local_point: point,
+            shape: self,
+            parent: None
         })
     }
 
@@ -145,11 +282,39 @@ impl<'a> Traceable for Sphere<'a> {
 
         Vec2::new(u, v)
     }
+
+    fn area(&self) -> f32 {
+        4.0*PI * self.radius*self.radius
+    }
+
+    /// Samples the cone in which the sphere is seen, points inside it are left to scattered rays
+    fn sample_towards(&self, point: Vec3, time: f32) -> Option<( Inter<&dyn Traceable>, f32 )> {
+        let to_center = self.pos - point;
+        let distance_squared = to_center.length_squared();
+        if distance_squared <= self.radius*self.radius { return None }
+
+        let cone = cone_height(self.radius*self.radius / distance_squared);
+
+        // Directions at the very edge of the cone can graze past the sphere by rounding, and are drawn again
+        let inter = (0..4).find_map(|_| {
+            let dir = random_in_cone(to_center / distance_squared.sqrt(), cone);
+            self.ray_intersection(&Ray { start: point, dir, time })
+        })?;
+
+        Some(( inter, 1.0 / (2.0*PI * cone) ))
+    }
+
+    fn pdf_towards(&self, point: Vec3, _time: f32, _inter: &Inter<&dyn Traceable>) -> f32 {
+        let distance_squared = self.pos.distance_squared(point);
+        if distance_squared <= self.radius*self.radius { return 0.0 }
+
+        1.0 / (2.0*PI * cone_height(self.radius*self.radius / distance_squared))
+    }
 }
 
-impl Traceable for Plane<'_> {
-    fn material(&self) -> &Material {
-        &self.material
+impl Traceable for Plane {
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
     }
 
     fn ray_intersection(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>> {
@@ -162,11 +327,19 @@ impl Traceable for Plane<'_> {
         let t = self.normal.dot(dist) / denom;
 
         if t >= 0.0 {
+            let front = denom < 0.0;
+            let normal = if front { self.normal } else { -self.normal };
+
+            let point = ray.start + ray.dir * t;
+
             Some( Inter {
-                point: ray.start + ray.dir * t,
-                normal: self.normal,
-                front: false,
-                shape: self
+                point,
+                normal,
+                geometric_normal: normal,
+                front,
+                local_point: point,
+                shape: self,
+                parent: None
             } )
         }
         else {
@@ -179,9 +352,9 @@ impl Traceable for Plane<'_> {
     }
 }
 
-impl<'a> Traceable for Triangle<'a> {
-    fn material(&self) -> &Material {
-        &self.material
+impl Traceable for Triangle {
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
     }
 
     fn ray_intersection(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>> {
@@ -206,17 +379,33 @@ impl<'a> Traceable for Triangle<'a> {
         if t > 0.0 {
             let point = ray.start + ray.dir * t;
 
-            let (w0, w1, w2) = self.barycentric_weigths(point);
-            let (w0, w1, w2) = (w0.max(0.0), w1.max(0.0), w2.max(0.0));
-            let normal = (self.p0.normal*0.3333 + self.p1.normal*0.33333 + self.p2.normal*0.33333).normalize();
+            let geometric_normal = self.edge1.cross(self.edge2).normalize();
 
-            let (normal, front) = if ray.dir.dot(normal) < 0.0 { ( normal, true ) } else { ( -normal, false ) };
+            // Phong shading, interpolate vertex normals with the barycentric coordinates u and v
+            let normal = if self.flat {
+                geometric_normal
+            }
+            else {
+                let interpolated = (self.p0.normal*(1.0 - u - v) + self.p1.normal*u + self.p2.normal*v).normalize_or_zero();
+
+                if interpolated == Vec3::ZERO { geometric_normal }
+                // Vertex normals are free to point to either side, keep them on the side of the surface
+                else if interpolated.dot(geometric_normal) < 0.0 { -interpolated }
+                else { interpolated }
+            };
+
+            // The side is decided by the actual surface, not by the interpolated normal
+            let front = ray.dir.dot(geometric_normal) < 0.0;
+            let sign = if front { 1.0 } else { -1.0 };
 
             Some(Inter {
                 point,
-                normal,
+                normal: normal * sign,
+                geometric_normal: geometric_normal * sign,
                 front,
-                shape: self
+                local_point: point,
+                shape: self,
+                parent: None
             })
         }
         else {
@@ -231,4 +420,47 @@ impl<'a> Traceable for Triangle<'a> {
 
         Vec2::new( out.x, out.y )
     }
+
+    fn color(&self, p: Vec3) -> Color {
+        match self.colors {
+            Some([c0, c1, c2]) => {
+                let (w0, w1, w2) = self.barycentric_weigths(p);
+                c0*w0 + c1*w1 + c2*w2
+            },
+            None => Color::WHITE
+        }
+    }
+
+    fn area(&self) -> f32 {
+        self.edge1.cross(self.edge2).length() / 2.0
+    }
+
+    /// Samples the area of the triangle uniformly
+    fn sample_towards(&self, point: Vec3, _time: f32) -> Option<( Inter<&dyn Traceable>, f32 )> {
+        let r1 = random::<f32>().sqrt();
+        let r2 = random::<f32>();
+        let target = self.p0.pos + self.edge1 * (r1 * (1.0 - r2)) + self.edge2 * (r1 * r2);
+
+        let to_target = target - point;
+        let distance_squared = to_target.length_squared();
+        let dir = to_target / distance_squared.sqrt();
+
+        let geometric_normal = self.edge1.cross(self.edge2).normalize();
+        let cosine = dir.dot(geometric_normal);
+        if cosine.is_zero() || !cosine.is_finite() { return None }
+
+        let front = cosine < 0.0;
+        let normal = if front { geometric_normal } else { -geometric_normal };
+
+        let inter = Inter { point: target, normal, geometric_normal: normal, front, local_point: target, shape: self as &dyn Traceable, parent: None };
+
+        Some(( inter, distance_squared / (self.area() * cosine.abs()) ))
+    }
+
+    fn pdf_towards(&self, point: Vec3, _time: f32, inter: &Inter<&dyn Traceable>) -> f32 {
+        let to_point = inter.point - point;
+        let cosine = to_point.normalize().dot(inter.geometric_normal).abs();
+
+        to_point.length_squared() / (self.area() * cosine).max(f32::EPSILON)
+    }
 }
@@ -0,0 +1,35 @@
+pub mod shape;
+pub mod bvh;
+pub mod intersection;
+pub mod material;
+pub mod light;
+pub mod sky;
+pub mod environment;
+pub mod reflect;
+pub mod texture;
+pub mod camera;
+pub mod motion;
+pub mod stereo;
+pub mod mesh;
+pub mod instance;
+pub mod stl;
+pub mod obj;
+pub mod ply;
+pub mod gltf_file;
+pub mod scene_file;
+pub mod scene;
+pub mod render;
+mod rng;
+mod distribution;
+
+#[cfg(test)]
+mod test;
+
+pub use shape::Ray;
+pub use material::{Color, Material, MaterialKind};
+pub use intersection::{Traceable, Inter};
+pub use texture::Texture;
+pub use camera::Camera;
+pub use light::Light;
+pub use scene::Scene;
+pub use render::{Renderer, PreparedScene};
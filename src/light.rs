@@ -0,0 +1,277 @@
+//! Light sources sampled explicitly by the integrator
+//!
+//! Powers are in watts and distances in meters, so that point and area lights fall off as they would in a real room.
+
+use std::{f32::consts::PI, sync::Arc};
+
+use glam::Vec3;
+
+use crate::{material::{Color, tangent_to_world_matrix}, environment::EnvironmentMap, shape::Ray, rng::random};
+
+/// Distant light covering a cone of the sky, seen as a disc
+#[derive(Debug, Clone, Copy)]
+pub struct Sun {
+    /// Normalized direction pointing towards the sun
+    pub direction: Vec3,
+    /// Cosine of the angle between the center and the edge of the disc
+    pub cos_radius: f32,
+    pub radiance: Color
+}
+
+impl Sun {
+    /// `angular_diameter` is the angle covered by the disc, in radians
+    pub fn new(direction: Vec3, angular_diameter: f32, radiance: Color) -> Self {
+        Sun { direction: direction.normalize(), cos_radius: (angular_diameter / 2.0).cos(), radiance }
+    }
+
+    /// Whether a direction points inside the disc
+    pub fn contains(&self, dir: Vec3) -> bool {
+        dir.dot(self.direction) >= self.cos_radius
+    }
+
+    /// Uniformly samples a direction inside the disc
+    pub fn sample(&self) -> Vec3 {
+        random_in_cone(self.direction, 1.0 - self.cos_radius)
+    }
+
+    /// Density of [`Sun::sample`] over solid angle
+    pub fn pdf(&self) -> f32 {
+        1.0 / (2.0*PI * (1.0 - self.cos_radius))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Light {
+    Sun(Sun),
+    /// Panorama around the scene, sampled according to its luminance
+    Environment(Arc<EnvironmentMap>),
+    /// Emits `power` watts evenly in every direction
+    Point { position: Vec3, color: Color, power: f32 },
+    /// Point light restricted to a cone of half angle `angle` around `direction`,
+    /// fading out over the outer `falloff` fraction of the cone
+    Spot { position: Vec3, direction: Vec3, angle: f32, falloff: f32, color: Color, power: f32 },
+    /// Parallelogram spanned by the edges `u` and `v`, emitting on the side of `u × v`
+    Rect { center: Vec3, u: Vec3, v: Vec3, color: Color, power: f32 },
+    /// Disc emitting on the side of `normal`
+    Disk { center: Vec3, normal: Vec3, radius: f32, color: Color, power: f32 },
+    /// Sphere emitting outwards
+    Sphere { center: Vec3, radius: f32, color: Color, power: f32 }
+}
+
+/// Light arriving at a point from a sampled direction
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    pub dir: Vec3,
+    /// Distance to the sampled point on the light, infinite for the sun
+    pub distance: f32,
+    /// Incident radiance, or irradiance for point and spot lights
+    pub radiance: Color,
+    /// Density over solid angle, `None` for point and spot lights which can only be reached by sampling them
+    pub pdf: Option<f32>
+}
+
+/// Hit of a ray on an area light
+#[derive(Debug, Clone, Copy)]
+pub struct LightHit {
+    pub distance: f32,
+    /// Radiance leaving the light towards the ray, black on the back of one-sided lights
+    pub radiance: Color
+}
+
+/// `1 - cos θ` for a cone with `sin² θ` given, without cancellation for the narrow cones of distant spheres
+pub(crate) fn cone_height(sin_squared: f32) -> f32 {
+    sin_squared / (1.0 + (1.0 - sin_squared).sqrt())
+}
+
+/// Uniformly samples a direction in a cone around `axis`, `height` being `1 - cos θ` for its half angle θ
+pub(crate) fn random_in_cone(axis: Vec3, height: f32) -> Vec3 {
+    let cos_theta = 1.0 - random::<f32>() * height;
+    let sin_theta = (1.0 - cos_theta*cos_theta).max(0.0).sqrt();
+    let phi = 2.0*PI*random::<f32>();
+
+    tangent_to_world_matrix(axis) * Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin())
+}
+
+/// Uniformly samples a point on a disc of radius 1 in the XZ plane
+fn random_in_disk() -> Vec3 {
+    let r = random::<f32>().sqrt();
+    let phi = 2.0*PI*random::<f32>();
+
+    Vec3::new(r * phi.cos(), 0.0, r * phi.sin())
+}
+
+impl Light {
+    pub fn new_point(position: Vec3, color: Color, power: f32) -> Self {
+        Light::Point { position, color, power }
+    }
+    /// `angle` is the half angle of the cone in radians and `falloff` ranges from 0 for a hard edge to 1 for a fade from the axis
+    pub fn new_spot(position: Vec3, direction: Vec3, angle: f32, falloff: f32, color: Color, power: f32) -> Self {
+        Light::Spot { position, direction: direction.normalize(), angle, falloff: falloff.clamp(0.0, 1.0), color, power }
+    }
+    pub fn new_rect(center: Vec3, u: Vec3, v: Vec3, color: Color, power: f32) -> Self {
+        Light::Rect { center, u, v, color, power }
+    }
+    pub fn new_disk(center: Vec3, normal: Vec3, radius: f32, color: Color, power: f32) -> Self {
+        Light::Disk { center, normal: normal.normalize(), radius, color, power }
+    }
+    pub fn new_sphere(center: Vec3, radius: f32, color: Color, power: f32) -> Self {
+        Light::Sphere { center, radius, color, power }
+    }
+
+    /// Radiance leaving the surface of an area light, as a perfectly diffuse emitter
+    fn area_radiance(color: Color, power: f32, area: f32) -> Color {
+        color * (power / (PI * area))
+    }
+
+    /// Samples the light as seen from `point`, `None` when no light can arrive from it
+    pub fn sample(&self, point: Vec3) -> Option<LightSample> {
+        match *self {
+            Light::Sun(sun) => Some(LightSample { dir: sun.sample(), distance: f32::INFINITY, radiance: sun.radiance, pdf: Some(sun.pdf()) }),
+            Light::Environment(ref map) => {
+                let ( dir, pdf ) = map.sample();
+
+                Some(LightSample { dir, distance: f32::INFINITY, radiance: map.radiance(dir), pdf: Some(pdf) })
+            },
+            Light::Point { position, color, power } | Light::Spot { position, color, power, .. } => {
+                let to_light = position - point;
+                let distance = to_light.length();
+                let dir = to_light / distance;
+
+                let mut intensity = color * (power / (4.0*PI));
+
+                if let Light::Spot { direction, angle, falloff, .. } = *self {
+                    let cos_theta = -dir.dot(direction);
+                    let ( cos_outer, cos_inner ) = ( angle.cos(), (angle * (1.0 - falloff)).cos() );
+
+                    if cos_theta <= cos_outer { return None }
+
+                    let t = ((cos_theta - cos_outer) / (cos_inner - cos_outer).max(f32::EPSILON)).min(1.0);
+                    intensity *= t*t*(3.0 - 2.0*t);
+                }
+
+                Some(LightSample { dir, distance, radiance: intensity / (distance*distance), pdf: None })
+            },
+            Light::Rect { center, u, v, color, power } => {
+                let normal = u.cross(v);
+                let area = normal.length();
+
+                let target = center + u * (random::<f32>() - 0.5) + v * (random::<f32>() - 0.5);
+                Light::sample_area(point, target, normal / area, area, Light::area_radiance(color, power, area))
+            },
+            Light::Disk { center, normal, radius, color, power } => {
+                let area = PI * radius*radius;
+
+                let target = center + tangent_to_world_matrix(normal) * random_in_disk() * radius;
+                Light::sample_area(point, target, normal, area, Light::area_radiance(color, power, area))
+            },
+            Light::Sphere { center, radius, color, power } => {
+                let to_center = center - point;
+                let distance_squared = to_center.length_squared();
+                if distance_squared <= radius*radius { return None }
+
+                // Uniformly samples the cone of directions in which the sphere is seen
+                let cone = cone_height(radius*radius / distance_squared);
+                let dir = random_in_cone(to_center / distance_squared.sqrt(), cone);
+
+                let hit = self.intersect(&Ray { start: point, dir, time: 0.0 })?;
+
+                Some(LightSample {
+                    dir,
+                    distance: hit.distance,
+                    radiance: Light::area_radiance(color, power, 4.0*PI * radius*radius),
+                    pdf: Some(1.0 / (2.0*PI * cone))
+                })
+            }
+        }
+    }
+
+    /// Turns a point uniformly sampled on a flat one-sided light into a sample over solid angle
+    fn sample_area(point: Vec3, target: Vec3, normal: Vec3, area: f32, radiance: Color) -> Option<LightSample> {
+        let to_light = target - point;
+        let distance = to_light.length();
+        let dir = to_light / distance;
+
+        let cos_light = -dir.dot(normal);
+        if cos_light <= 0.0 { return None }
+
+        Some(LightSample { dir, distance, radiance, pdf: Some(distance*distance / (area * cos_light)) })
+    }
+
+    /// Density with which [`Light::sample`] picks `dir` from `point`, for a direction that reaches the light
+    pub fn pdf(&self, point: Vec3, dir: Vec3, distance: f32) -> f32 {
+        match *self {
+            Light::Sun(sun) => if sun.contains(dir) { sun.pdf() } else { 0.0 },
+            Light::Environment(ref map) => map.pdf(dir),
+            Light::Point { .. } | Light::Spot { .. } => 0.0,
+            Light::Rect { u, v, .. } => {
+                let normal = u.cross(v);
+                let area = normal.length();
+
+                distance*distance / (area * (-dir.dot(normal / area)).max(f32::EPSILON))
+            },
+            Light::Disk { normal, radius, .. } => {
+                distance*distance / (PI * radius*radius * (-dir.dot(normal)).max(f32::EPSILON))
+            },
+            Light::Sphere { center, radius, .. } => {
+                let distance_squared = center.distance_squared(point);
+                if distance_squared <= radius*radius { return 0.0 }
+
+                1.0 / (2.0*PI * cone_height(radius*radius / distance_squared))
+            }
+        }
+    }
+
+    /// Intersects a ray with the surface of an area light, the sun and environment being found by rays leaving the scene
+    pub fn intersect(&self, ray: &Ray) -> Option<LightHit> {
+        match *self {
+            Light::Sun(_) | Light::Environment(_) | Light::Point { .. } | Light::Spot { .. } => None,
+            Light::Rect { center, u, v, color, power } => {
+                let normal = u.cross(v);
+                let area = normal.length();
+
+                let distance = Light::intersect_plane(ray, center, normal)?;
+                let local = ray.start + ray.dir * distance - center;
+
+                // Coordinates along the edges, from the dual basis of u and v in the plane
+                let ( uu, uv, vv ) = ( u.dot(u), u.dot(v), v.dot(v) );
+                let det = uu*vv - uv*uv;
+                let a = (vv * local.dot(u) - uv * local.dot(v)) / det;
+                let b = (uu * local.dot(v) - uv * local.dot(u)) / det;
+                if a.abs() > 0.5 || b.abs() > 0.5 { return None }
+
+                Some(Light::one_sided(ray, distance, normal, Light::area_radiance(color, power, area)))
+            },
+            Light::Disk { center, normal, radius, color, power } => {
+                let distance = Light::intersect_plane(ray, center, normal)?;
+                if (ray.start + ray.dir * distance).distance_squared(center) > radius*radius { return None }
+
+                Some(Light::one_sided(ray, distance, normal, Light::area_radiance(color, power, PI * radius*radius)))
+            },
+            Light::Sphere { center, radius, color, power } => {
+                let to_center = center - ray.start;
+                let b = to_center.dot(ray.dir);
+                let discriminant = b*b - to_center.length_squared() + radius*radius;
+                if discriminant < 0.0 { return None }
+
+                let distance = b - discriminant.sqrt();
+                if distance <= 0.0 { return None }
+
+                Some(LightHit { distance, radiance: Light::area_radiance(color, power, 4.0*PI * radius*radius) })
+            }
+        }
+    }
+
+    fn intersect_plane(ray: &Ray, center: Vec3, normal: Vec3) -> Option<f32> {
+        let denom = ray.dir.dot(normal);
+        if denom == 0.0 { return None }
+
+        let distance = (center - ray.start).dot(normal) / denom;
+        (distance > 0.0).then_some(distance)
+    }
+
+    fn one_sided(ray: &Ray, distance: f32, normal: Vec3, radiance: Color) -> LightHit {
+        let front = ray.dir.dot(normal) < 0.0;
+
+        LightHit { distance, radiance: if front { radiance } else { Color::BLACK } }
+    }
+}
@@ -1,322 +1,340 @@
-use std::{error::Error, f32::consts::PI, sync::atomic::AtomicUsize, io::{Seek, Read}, mem};
-use glam::{ Vec2, Vec3, Quat, Mat3, Mat4 };
-use image::{RgbImage, Rgb, buffer::PixelsMut};
-use itertools::Itertools;
-use rayon::prelude::*;
-
-mod shape;
-mod bvh;
-mod intersection;
-mod material;
-mod reflect;
-mod texture;
-
-use intersection::{Intersection, Inter};
-use texture::*;
-use lerp::Lerp;
-use material::Color;
-use rand::{thread_rng, Rng, random};
-use shape::*;
-use bvh::Bvh;
-
-use crate::{
-    intersection::Traceable,
-    material::{Material, MaterialKind}
-};
-
-// 144s
-
-
-#[derive(Debug)]
-struct Camera {
-    position: Vec3,
-    orientation: Quat
-}
-
-/// Returns the ray passing through a pixel given its position
-fn pixel_as_ray(canvas: &RgbImage, camera: &Camera, x: f32, y: f32, fov: f32) -> Ray {
-    let pos = Vec2::new(x, y);
-
-    let canvas_size = Vec2::new(canvas.width() as f32, canvas.height() as f32);
-
-    let normalized_coordinates = pos / canvas_size * 2.0 - Vec2::ONE; // Range -1..1
+use std::{error::Error, path::PathBuf, time::Instant};
+use clap::{Parser, Subcommand, Args, ValueEnum};
 
-    let aspect_ratio = canvas_size.x / canvas_size.y;
+use raytracing::{Scene, Renderer, PreparedScene, bvh::Bvh, camera::{FieldOfView, pixel_as_ray}, stereo::{self, StereoRig, StereoLayout}};
 
-    let ray_dir = Vec2::new(normalized_coordinates.x * aspect_ratio * fov, -normalized_coordinates.y * fov);
+#[derive(Debug, Parser)]
+#[command(version, about = "A small path tracer rendering TOML scene files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
 
-    Ray {
-        start: camera.position,
-        dir: camera.orientation.mul_vec3(Vec3::new(ray_dir.x, ray_dir.y, 1.0).normalize())
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Render a scene to an image file
+    Render {
+        #[command(flatten)]
+        settings: RenderSettings,
+
+        /// Image file to write, the format is deduced from the extension
+        #[arg(short, long, default_value = "output.png")]
+        output: PathBuf
+    },
+    /// Render a numbered sequence of frames of an animated scene
+    Animate {
+        #[command(flatten)]
+        settings: RenderSettings,
+
+        /// Path of the frames, the run of `#` is replaced by the zero-padded frame number
+        #[arg(short, long, default_value = "frame_####.png")]
+        output: String,
+
+        /// Frames per second, keyframe times being in seconds
+        #[arg(long, default_value_t = 24.0, value_parser = positive)]
+        fps: f32,
+
+        /// First frame to render
+        #[arg(long, default_value_t = 0)]
+        start: u32,
+
+        /// Last frame to render, included
+        #[arg(long)]
+        end: u32
+    },
+    /// Render a stereo pair, in omnidirectional stereo for equirectangular cameras
+    Stereo {
+        #[command(flatten)]
+        settings: RenderSettings,
+
+        /// Image file to write, separate eyes get `_left` and `_right` appended to the name
+        #[arg(short, long, default_value = "stereo.png")]
+        output: PathBuf,
+
+        /// Distance between the eyes, in scene units
+        #[arg(long, default_value_t = 0.065)]
+        interocular: f32,
+
+        /// Distance of the plane appearing at the depth of the screen, parallel views when absent
+        #[arg(long, value_parser = positive)]
+        convergence: Option<f32>,
+
+        #[arg(long, value_enum, default_value_t = Layout::SideBySide)]
+        layout: Layout
+    },
+    /// Print a summary of a scene without rendering it
+    Info {
+        /// Scene file to inspect
+        scene: PathBuf
+    },
+    /// Render a scene several times and report timings, without saving the image
+    Bench {
+        #[command(flatten)]
+        settings: RenderSettings,
+
+        /// Number of timed renders
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        runs: u32,
+
+        /// Compare the BVH builders by tracing one camera ray per pixel through each, instead of rendering
+        #[arg(long)]
+        bvh: bool
     }
-
 }
 
-fn intersection<'a>(scene: &'a [&'a dyn Traceable], ray: &'a Ray) -> Option<Inter<&'a dyn Traceable>> {
-    scene.iter()
-        .filter_map(|shape| {
-            shape.ray_intersection(ray)
-        })
-        .min_by(|a, b|{
-            a.point.distance_squared(ray.start).partial_cmp(&b.point.distance_squared(ray.start)).unwrap()
-        })
+/// Arrangement of the eyes of a stereo pair
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Layout {
+    SideBySide,
+    TopBottom,
+    /// One image per eye
+    Separate
 }
 
-fn random_vector_in_hemisphere(normal: Vec3) -> Vec3 {
-    // Sample point on local hemisphere
-    let r1: f32 = thread_rng().gen_range(0.0..1.0);
-    let r2: f32 = thread_rng().gen_range(0.0..1.0);
-
-    let sin_theta = ( 1.0 - r1*r1 ).sqrt();
-    let phi = 2.0*PI*r2;
-    let x = sin_theta * phi.cos();
-    let z = sin_theta * phi.sin();
+#[derive(Debug, Args)]
+struct RenderSettings {
+    /// Scene file to render
+    scene: PathBuf,
 
-    let sample = Vec3::new(x, r1, z);
+    /// Width of the image in pixels
+    #[arg(long, default_value_t = 800, value_parser = clap::value_parser!(u32).range(1..))]
+    width: u32,
 
-    // Construct coordinate system aligned to normal
-    let n_t = if normal.x.abs() > normal.y.abs() {
-        Vec3::new(normal.z, 0.0, -normal.x)
-    }
-    else {
-        Vec3::new(0.0, -normal.z, normal.y)
-    }.normalize();
-
-    let n_b = normal.cross(n_t);
+    /// Height of the image in pixels
+    #[arg(long, default_value_t = 400, value_parser = clap::value_parser!(u32).range(1..))]
+    height: u32,
 
-    // Transform(rotate) sample into normal coordinate space
-    let matrix = Mat3::from_cols(n_b, normal, n_t);
-
-    matrix * sample
-}
+    /// Number of samples per pixel
+    #[arg(short, long, default_value_t = 2048, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: u32,
 
-fn trace(scene: &Bvh, directional_light: &Vec3, ray: Ray, count: i32) -> Color {
-    const MAX_COUNT: i32 = 7;
+    /// Maximum number of bounces of a ray
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u32).range(1..))]
+    max_depth: u32,
 
-    if count >= MAX_COUNT { return Color::BLACK }
+    /// Number of worker threads, defaults to the number of cores
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
 
-    let intensity = 30.0f32;
+    /// Seed of the random number generator, making renders reproducible
+    #[arg(long)]
+    seed: Option<u64>,
 
-    if let Some(inter) = scene.intersects(&ray) {
-        let material = inter.shape.material();
-
-        // let direct: Color = (0..3).into_iter().map(|_| {
-        //     let towards_light = Ray { start: ray.start, dir: (*directional_light + Vec3::new(random(), random(), random())/10.0).normalize() }.offset();
-        //
-        //     if let ( MaterialKind::Lambertian { .. }, None ) = ( material.kind, scene.intersects(&towards_light) ) {
-        //         Color::WHITE * inter.normal.dot(towards_light.dir).max(0.0) * intensity
-        //     } else {
-        //         Color::BLACK
-        //     }
-        // }).reduce(|a, b| { a + b }).unwrap() / 3.0;
+    /// Don't print progress
+    #[arg(short, long)]
+    quiet: bool
+}
 
-        let ( ray, attenuation ) = material.scatter(&ray, &inter);
+impl RenderSettings {
+    fn renderer(&self) -> Result<Renderer, Box<dyn Error>> {
+        let mut renderer = Renderer::new(self.width, self.height)
+            .set_samples(self.samples)
+            .set_max_depth(self.max_depth)
+            .set_seed(self.seed);
 
-        if let Some(ray) = ray {
-            let indirect = trace(scene, directional_light, ray.offset(), count + 1);
-            indirect * attenuation
+        if let Some(threads) = self.threads {
+            renderer = renderer.set_threads(threads as usize)?;
         }
-        else {
-            attenuation
+        if !self.quiet {
+            renderer = renderer.set_progress(|done| println!("{} % done", (done * 100.0).round()));
         }
-    }
-    else {
-        let shadow = ray.dir.dot(*directional_light);
 
-        // let direct = Color::WHITE * ray.dir.dot(*directional_light).max(0.0) * intensity;
-        let sky = Color::new(0.1, 0.4, 0.7).lerp(Color::new(0.7, 0.8, 0.9), ray.dir.y/2.0 + 0.5); // Whiter towards top and bluer towards bottom
-
-        // return direct + sky;
-
-        if shadow >= 0.95 {
-            Color::WHITE * intensity + sky
-        }
-        else {
-            sky
-        }
+        Ok(renderer)
     }
 }
 
-/// Creates a z-axis aligned rectangle out of two triangles
-fn square( center: Vec3, size: Vec2, orientation: Quat, material: Material ) -> ( Triangle, Triangle ) {
-    let p1 = orientation * Vec3::new(-size.x/2.0, 0.0, -size.y/2.0);
-    let p2 = orientation * Vec3::new( size.x/2.0, 0.0, -size.y/2.0);
-    let p3 = orientation * Vec3::new(-size.x/2.0, 0.0,  size.y/2.0);
-    let p4 = orientation * Vec3::new( size.x/2.0, 0.0,  size.y/2.0);
-
-    let p1 = Vertex { pos: center + p1, tex: Vec2::new(0.0, 1.0), ..Default::default() };
-    let p2 = Vertex { pos: center + p2, tex: Vec2::new(1.0, 1.0), ..Default::default() };
-    let p3 = Vertex { pos: center + p3, tex: Vec2::new(0.0, 0.0), ..Default::default() };
-    let p4 = Vertex { pos: center + p4, tex: Vec2::new(1.0, 0.0), ..Default::default() };
-
-    (
-        Triangle::new( p1, p2, p3, material.clone() ),
-        Triangle::new( p2, p3, p4, material )
-    )
+/// Parses a number strictly greater than zero
+fn positive(arg: &str) -> Result<f32, String> {
+    match arg.parse::<f32>() {
+        Ok(value) if value > 0.0 => Ok(value),
+        Ok(_) => Err("must be greater than 0".to_owned()),
+        Err(e) => Err(e.to_string())
+    }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-#[repr(C, packed)]
-struct StlTriangle {
-    normal: [f32; 3],
-    v0: [f32; 3],
-    v1: [f32; 3],
-    v2: [f32; 3],
-    attribute: u16
+fn load_scene(path: &PathBuf) -> Scene {
+    match Scene::from_file(path) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
-fn load_stl_file<P: AsRef<std::path::Path>>(file: P) -> std::io::Result<Vec<Triangle<'static>>> {
-
-    let mut data = std::fs::File::open(file)?;
-    data.seek(std::io::SeekFrom::Current(80))?;
-
-    let mut num_triangles: [u8; 4] = [0; 4];
-    data.read_exact(&mut num_triangles)?;
-    let num_triangles = u32::from_le_bytes(num_triangles);
-
-    let mut triangles = Vec::new();
-
-    for _ in 0..num_triangles {
-        let mut t = StlTriangle::default();
-        unsafe {
-            let buffer: &mut [u8] = std::slice::from_raw_parts_mut(
-                (&mut t as *mut StlTriangle).cast(), 
-                mem::size_of::<StlTriangle>()
-            );
-
-            data.read_exact(buffer)?;
+/// Replaces the run of `#` in `pattern` with the frame number, or appends it before the extension when there is none
+fn frame_path(pattern: &str, frame: u32) -> PathBuf {
+    match pattern.find('#') {
+        Some(start) => {
+            let width = pattern[start..].chars().take_while(|&c| c == '#').count();
+            format!("{}{:0width$}{}", &pattern[..start], frame, &pattern[start + width..]).into()
+        },
+        None => {
+            let path = PathBuf::from(pattern);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match path.extension() {
+                Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+                None => format!("{}_{:04}", stem, frame)
+            };
+
+            path.with_file_name(name)
         }
-
-        triangles.push( Triangle::new( 
-            Vertex { pos: Vec3::from_array(t.v0), normal: Vec3::from_array(t.normal), tex: Vec2::ZERO }, 
-            Vertex { pos: Vec3::from_array(t.v1), normal: Vec3::from_array(t.normal), tex: Vec2::ZERO }, 
-            Vertex { pos: Vec3::from_array(t.v2), normal: Vec3::from_array(t.normal), tex: Vec2::ZERO }, 
-            Material::new_lambertian(Color::WHITE) 
-        ))
     }
-
-    Ok(triangles)
 }
 
-fn aces(x: Color) -> Color {
-    let a = 2.51f32;
-    let b = 0.03f32;
-    let c = 2.43f32;
-    let d = 0.59f32;
-    let e = 0.14f32;
+/// Times building the median split and surface area heuristic hierarchies, and tracing camera rays through them
+fn compare_bvh(scene: &Scene, settings: &RenderSettings, runs: u32) {
+    let ( width, height ) = ( settings.width, settings.height );
+    let rays: Vec<_> = (0..height).flat_map(|y| (0..width).map(move |x| ( x, y )))
+        .filter_map(|( x, y )| pixel_as_ray(width, height, &scene.camera, x as f32 + 0.5, y as f32 + 0.5, 0.0))
+        .collect();
+
+    for name in [ "median", "sah" ] {
+        let shapes_ref: Vec<_> = scene.shapes().iter().map(Box::as_ref).collect();
+
+        let start = Instant::now();
+        let bvh = if name == "median" { Bvh::construct(shapes_ref, 0) } else { Bvh::build(shapes_ref) };
+        let built = start.elapsed().as_secs_f64();
+
+        let mut total = 0.0;
+        let mut hits = 0;
+        for _ in 0..runs {
+            let start = Instant::now();
+            hits = rays.iter().filter(|ray| bvh.intersects(ray).is_some()).count();
+            total += start.elapsed().as_secs_f64();
+        }
 
-    (x*(x*a + b))/(x*(x*c + d) + e)
+        let average = total / runs as f64;
+        println!("{}: built in {:.3} s, depth {}, cost {:.1}, {} hits, {:.3} s ({:.2} Mrays/s)",
+            name, built, bvh.depth(), bvh.cost(), hits, average, rays.len() as f64 / average / 1e6);
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
 
-    // let image = Texture::from_file("/home/davawen/Pictures/funi.png")?;
-    // let earth = Texture::from_file("/home/davawen/Pictures/earth.jpg")?;
-    let earth_normal = Texture::from_file("/home/davawen/Pictures/2k_earth_normal_map.tif")?;
-    let rusty_metal_norm = Texture::from_file("/home/davawen/Pictures/3314-normal.jpg")?;
-    let bumpy_grid_norm = Texture::from_file("/home/davawen/Pictures/metal.png")?.set_wrapping(TextureWrapping::Repeat);
-    let bumpy_norm = Texture::from_file("/home/davawen/Pictures/bumpy_normal.jpg")?.set_wrapping(TextureWrapping::MirroredRepeat);
-    let scratched_norm = Texture::from_file("/home/davawen/Pictures/reduced.png")?.set_wrapping(TextureWrapping::MirroredRepeat);
-
-    let mut shapes: Vec<Box<dyn Traceable>> = vec![
-        Box::new(Plane {
-            pos: Vec3::new(0.0, 0.0, 0.0),
-            normal: Vec3::new(0.0, 1.0, 0.0),
-            material: Material::new_lambertian(Color::new(0.6, 0.2, 0.0)).set_normal(&bumpy_grid_norm).set_size(( 1.0/20.0, 1.0/20.0 )) /* Material::new_lambertian(Color::new( 0.8, 0.4, 0.0 )) */
-        })
-    ];
-
-    let dog = load_stl_file("/home/davawen/Documents/monke.stl").unwrap();
-    let mat = Mat4::from_translation(Vec3::new(20.0, 10.0, -10.0)) * Mat4::from_rotation_y(PI/2.0) * Mat4::from_rotation_x(-PI/2.0) * Mat4::from_rotation_z(PI/1.7) * Mat4::from_scale(Vec3::splat(8.0));
-
-    for mut t in dog { 
-        t = t.transform(mat);
-        // t.material = Material::new_transparent(1.31);
-        t.material = Material::new_lambertian(Color::WHITE * 0.9);
-        // t.material = Material::new_metal(Color::RED);
-        
-        shapes.push(Box::new(t)) 
-    }
-
-    macro_rules! square {
-        ($a:expr, $b: expr, $c: expr, $d: expr) => {
-            let p = square($a, $b, $c, $d);
-            shapes.push(Box::new(p.0));
-            shapes.push(Box::new(p.1));
-        }
-    }
-
-    shapes.push(Box::new(Sphere {
-        pos: Vec3::new(-15.0, 10.0, 20.0),
-        radius: 12.0,
-        material: Material::new_lambertian(Color::WHITE)
-    }));
-    shapes.push(Box::new(Sphere {
-        pos: Vec3::new(50.0, 14.0, -10.0),
-        radius: 7.0,
-        material: Material::new_metal(Color::GRAY)
-    }));
-    shapes.push(Box::new(Sphere {
-        pos: Vec3::new(5.0, 5.0, -10.0),
-        radius: 5.0,
-        material: Material::new_transparent(1.52)
-    }));
-
-    let fov = 90.0_f32.to_radians();
-
-    let camera = Camera {
-        position: Vec3::new(20.0, 20.0, -30.0),
-        orientation: Quat::from_rotation_x(0.2)
-    };
-
-    let light_source = Vec3::new(-1.0, 1.0, -1.0).normalize();
-
-
-    let mut canvas = RgbImage::new(800, 400);
+    match cli.command {
+        Command::Render { settings, output } => {
+            let scene = load_scene(&settings.scene);
 
-    let mut shapes_ref: Vec<_> = shapes.iter().map(Box::as_ref).collect();
+            let canvas = settings.renderer()?.render(&scene);
 
-    let bvh = Bvh::construct(&mut shapes_ref, 0);
-
-    unsafe {
-        let count: AtomicUsize = AtomicUsize::new(0);
-        let count_fraction = (canvas.width() * canvas.height() / 10) as usize;
+            canvas.save(&output)?;
+        },
+        Command::Animate { settings, output, fps, start, end } => {
+            if start > end {
+                eprintln!("error: the first frame {} is after the last frame {}", start, end);
+                std::process::exit(1);
+            }
 
-        const NUM_SAMPLES: usize = 2048;
+            let scene = load_scene(&settings.scene);
+            let prepared = PreparedScene::new(&scene);
+            let renderer = settings.renderer()?;
 
-        let _canvas = (&mut canvas) as *mut RgbImage; // Ignore borrow checking, we know writes don't alias
+            for frame in start..=end {
+                let path = frame_path(&output, frame);
+                if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(parent)?;
+                }
 
-        (*_canvas).enumerate_pixels_mut().par_bridge().for_each(|(x, y, pixel)| {
-            let mut color = Color::BLACK;
+                renderer.render_frame(&prepared, frame as f32 / fps).save(&path)?;
 
-            for _ in 0..NUM_SAMPLES {
-                // Random direction through pixel for antialiasing
-                let ray = pixel_as_ray(&canvas, &camera, x as f32 + random::<f32>(), y as f32 + random::<f32>(), fov);
+                if !settings.quiet {
+                    println!("frame {} written to {}", frame, path.display());
+                }
+            }
+        },
+        Command::Stereo { settings, output, interocular, convergence, layout } => {
+            let scene = load_scene(&settings.scene);
 
-                color += trace(&bvh, &light_source, ray, 0);
+            let mut rig = StereoRig::new(interocular);
+            if let Some(convergence) = convergence {
+                rig = rig.set_convergence(convergence)?;
             }
 
-            color /= NUM_SAMPLES as f32;
+            let [left, right] = settings.renderer()?.render_stereo(&PreparedScene::new(&scene), &rig, 0.0);
 
-            color = aces(color);
+            match layout {
+                Layout::SideBySide => stereo::combine(&left, &right, StereoLayout::SideBySide).save(&output)?,
+                Layout::TopBottom => stereo::combine(&left, &right, StereoLayout::TopBottom).save(&output)?,
+                Layout::Separate => {
+                    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+                    let extension = output.extension().unwrap_or_default().to_string_lossy();
 
-            color.r = color.r.min(1.0);
-            color.g = color.g.min(1.0);
-            color.b = color.b.min(1.0);
+                    left.save(output.with_file_name(format!("{}_left.{}", stem, extension)))?;
+                    right.save(output.with_file_name(format!("{}_right.{}", stem, extension)))?;
+                }
+            }
+        },
+        Command::Info { scene: path } => {
+            let scene = load_scene(&path);
+
+            println!("scene: {}", path.display());
+            println!("camera: position {}, orientation {}, {}", scene.camera.position, scene.camera.orientation, match scene.camera.fov {
+                FieldOfView::Vertical(fov) => format!("vertical fov {:.1}°", fov.to_degrees()),
+                FieldOfView::Horizontal(fov) => format!("horizontal fov {:.1}°", fov.to_degrees())
+            });
+            println!("projection: {:?}", scene.camera.projection);
+            if let Some(animation) = &scene.camera.animation {
+                println!("camera animation: {} keyframes", animation.keyframes().len());
+            }
+            if scene.camera.aperture > 0.0 {
+                println!("lens: aperture {}, focus distance {}", scene.camera.aperture, scene.camera.focus_distance);
+            }
+            match &scene.environment {
+                Some(map) => {
+                    let ( width, height ) = map.dimensions();
+                    println!("environment: {}x{}, rotation {:.1}°, intensity {}", width, height, map.rotation().to_degrees(), map.intensity());
+                },
+                None => println!("sun: direction {}, turbidity {}", scene.sky.sun_direction(), scene.sky.turbidity())
+            }
+            if !scene.lights.is_empty() {
+                println!("lights: {}", scene.lights.len());
+            }
+            println!("primitives: {}", scene.shapes().len());
+
+            let shapes_ref: Vec<_> = scene.shapes().iter().map(Box::as_ref).collect();
+            if !shapes_ref.is_empty() {
+                let bvh = Bvh::build(shapes_ref);
+
+                println!("bvh depth: {}, cost {:.1}", bvh.depth(), bvh.cost());
+                let bound = bvh.bound();
+                println!("bounds: {} to {}", bound.min, bound.max);
+                if !bvh.unbounded().is_empty() {
+                    println!("unbounded primitives: {}", bvh.unbounded().len());
+                }
+            }
+        },
+        Command::Bench { settings, runs, bvh } => {
+            let scene = load_scene(&settings.scene);
+
+            if bvh {
+                if scene.shapes().is_empty() {
+                    eprintln!("error: the scene has no shapes");
+                    std::process::exit(1);
+                }
+
+                compare_bvh(&scene, &settings, runs);
+                return Ok(());
+            }
+            let renderer = settings.renderer()?;
 
-            *pixel = color.into();
+            let mut total = 0.0;
+            for run in 1..=runs {
+                let start = Instant::now();
+                renderer.render(&scene);
+                let elapsed = start.elapsed().as_secs_f64();
 
-            let val = count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            if val % count_fraction == 0 {
-                println!("{} % done", val/count_fraction * 10);
+                println!("run {}: {:.3} s", run, elapsed);
+                total += elapsed;
             }
-        });
-    }
 
-    // Gamma correction
-    canvas.iter_mut().for_each(|p| *p = (((*p as f64) / 256.0).sqrt() * 256.0) as u8 );
+            let average = total / runs as f64;
+            let samples = settings.width as f64 * settings.height as f64 * settings.samples as f64;
 
-    canvas.save("output.png")?;
+            println!("average: {:.3} s ({:.2} Msamples/s)", average, samples / average / 1e6);
+        }
+    }
 
     Ok(())
 }
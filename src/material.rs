@@ -1,10 +1,10 @@
-use std::{ops::Mul, f32::consts::PI};
+use std::{ops::Mul, f32::consts::PI, sync::Arc};
 
-use crate::{shape::Ray, intersection::{Inter, Traceable}, reflect::Reflect, texture::Texture};
+use crate::{shape::Ray, intersection::{Inter, Traceable}, reflect::Reflect, texture::Texture, rng::random};
 use derive_more::{ Add, AddAssign, Mul, MulAssign, Sub, SubAssign, Div, DivAssign };
 use glam::{Vec3, Mat3, Vec3Swizzles, Vec2};
 use image::Rgb;
-use rand::{Rng, prelude::Distribution, distributions::Standard, random};
+use rand::{Rng, prelude::Distribution, distributions::Standard};
 
 #[derive(Debug, Clone, Copy, Add, AddAssign, Mul, MulAssign, Sub, SubAssign, Div, DivAssign)]
 pub struct Color {
@@ -94,6 +94,12 @@ impl_from_ref!(Rgb<u8>, Color, p, {
     Color::from_u8(p[0], p[1], p[2])
 });
 
+impl_from_ref!(Rgb<f32>, Color, p, {
+    let p = &p.0;
+
+    Color::new(p[0], p[1], p[2])
+});
+
 #[allow(clippy::from_over_into)]
 impl Into<Vec3> for Color {
     fn into(self) -> Vec3 {
@@ -128,10 +134,27 @@ impl Distribution<Color> for Standard {
     }
 }
 
+/// Outcome of [`Material::scatter`]
+#[derive(Debug)]
+pub struct Scatter {
+    /// Next ray of the path, `None` when it stops here
+    pub ray: Option<Ray>,
+    /// BSDF times the cosine term divided by the density of the ray, or the emitted light when the path stops
+    pub attenuation: Color,
+    /// Density of the direction of the ray over solid angle, `None` for specular directions that can't be picked otherwise
+    pub pdf: Option<f32>
+}
+
+impl Scatter {
+    fn absorbed() -> Self {
+        Scatter { ray: None, attenuation: Color::BLACK, pdf: None }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct Material<'a> {
-    texture: Option<&'a Texture>,
-    normal_map: Option<&'a Texture>,
+pub struct Material {
+    texture: Option<Arc<Texture>>,
+    normal_map: Option<Arc<Texture>>,
     texture_size: Vec2,
     pub kind: MaterialKind
 }
@@ -140,12 +163,14 @@ pub struct Material<'a> {
 #[allow(unused)]
 pub enum MaterialKind {
     Lambertian { albedo: Color },
-    Metal { albedo: Color },
+    /// `roughness` ranges from 0 for a perfect mirror to 1 for a very blurry reflection
+    Metal { albedo: Color, roughness: f32 },
     Transparent { refraction_index: f32 },
-    Emmitive { color: Color, intensity: f32 }
+    /// Emits on the front of the surface, or on both sides when `two_sided` is set
+    Emmitive { color: Color, intensity: f32, two_sided: bool }
 }
 
-impl Default for Material<'_> {
+impl Default for Material {
     fn default() -> Self {
         Material {
             texture: None,
@@ -156,7 +181,7 @@ impl Default for Material<'_> {
     }
 }
 
-fn tangent_to_world_matrix(normal: Vec3) -> Mat3 {
+pub(crate) fn tangent_to_world_matrix(normal: Vec3) -> Mat3 {
     let n_t = if normal.x.abs() > normal.y.abs() {
         Vec3::new(normal.z, 0.0, -normal.x)
     }
@@ -169,42 +194,62 @@ fn tangent_to_world_matrix(normal: Vec3) -> Mat3 {
     Mat3::from_cols(n_b, normal, n_t)
 }
 
-fn random_vector_in_hemisphere(tangent_matrix: Mat3) -> Vec3 {
-    // Sample point on local hemisphere
+/// Samples the hemisphere with a density proportional to the cosine of the angle to the normal, matching the Lambertian BRDF
+fn random_cosine_vector(tangent_matrix: Mat3) -> Vec3 {
     let r1: f32 = random();
     let r2: f32 = random();
 
-    let sin_theta = ( 1.0 - r1*r1 ).sqrt();
+    let cos_theta = r1.sqrt();
+    let sin_theta = ( 1.0 - r1 ).sqrt();
     let phi = 2.0*PI*r2;
-    let x = sin_theta * phi.cos();
-    let z = sin_theta * phi.sin();
 
-    let sample = Vec3::new(x, r1, z);
+    let sample = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
 
     // Transform(rotate) sample into normal coordinate space
     tangent_matrix * sample
 }
 
-impl<'a> Material<'a> {
+/// Exponent of the Phong lobe standing for a roughness
+fn phong_exponent(roughness: f32) -> f32 {
+    (2.0 / (roughness*roughness) - 2.0).max(0.0)
+}
+
+/// Samples a Phong lobe centered on `axis`, whose exponent is derived from a roughness
+fn random_vector_in_lobe(axis: Vec3, roughness: f32) -> Vec3 {
+    let exponent = phong_exponent(roughness);
+
+    let cos_theta = random::<f32>().powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+    let phi = 2.0*PI*random::<f32>();
+
+    let sample = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+
+    tangent_to_world_matrix(axis) * sample
+}
+
+impl Material {
     pub fn new_lambertian(albedo: Color) -> Self {
         Material { kind: MaterialKind::Lambertian { albedo }, ..Default::default() }
     }
     pub fn new_metal(albedo: Color) -> Self {
-        Material { kind: MaterialKind::Metal { albedo }, ..Default::default() }
+        Material { kind: MaterialKind::Metal { albedo, roughness: 0.0 }, ..Default::default() }
+    }
+    pub fn new_rough_metal(albedo: Color, roughness: f32) -> Self {
+        Material { kind: MaterialKind::Metal { albedo, roughness }, ..Default::default() }
     }
     pub fn new_transparent(refraction_index: f32) -> Self {
         Material { kind: MaterialKind::Transparent { refraction_index }, ..Default::default() }
     }
     pub fn new_emmitive(color: Color, intensity: f32) -> Self {
-        Material { kind: MaterialKind::Emmitive { color, intensity }, ..Default::default() }
+        Material { kind: MaterialKind::Emmitive { color, intensity, two_sided: true }, ..Default::default() }
     }
 
 
-    pub fn set_texture(mut self, texture: &'a Texture) -> Self {
+    pub fn set_texture(mut self, texture: Arc<Texture>) -> Self {
         self.texture = Some(texture);
         self
     }
-    pub fn set_normal(mut self, texture: &'a Texture) -> Self {
+    pub fn set_normal(mut self, texture: Arc<Texture>) -> Self {
         self.normal_map = Some(texture);
         self
     }
@@ -212,24 +257,43 @@ impl<'a> Material<'a> {
         self.texture_size = Vec2::new(size.0, size.1);
         self
     }
+    /// Makes emissive materials emit on both sides of their surface, or only on the front. Other materials are left as they are
+    pub fn set_two_sided(mut self, two_sided: bool) -> Self {
+        if let MaterialKind::Emmitive { two_sided: ref mut sides, .. } = self.kind {
+            *sides = two_sided;
+        }
+        self
+    }
 
-    pub fn scatter(&self, ray: &Ray, inter: &Inter<&dyn Traceable>) -> ( Option<Ray>, Color) {
-        use MaterialKind::*;
+    /// Light emitted at an intersection towards the ray that found it, modulated by the texture, `None` for materials that don't emit
+    pub fn emitted(&self, inter: &Inter<&dyn Traceable>) -> Option<Color> {
+        match self.kind {
+            MaterialKind::Emmitive { color, intensity, two_sided } => {
+                if !two_sided && !inter.front { return Some(Color::BLACK) }
 
-        let tex = if let Some(image) = self.texture { 
-            let Vec2{ x: u, y: v } = inter.shape.sample(inter.point) * self.texture_size;
+                let ( tex, _ ) = self.surface(inter);
+                Some(color * tex * intensity)
+            },
+            _ => None
+        }
+    }
+
+    /// Color of the texture and shading normal, after normal mapping, at an intersection
+    fn surface(&self, inter: &Inter<&dyn Traceable>) -> ( Color, Vec3 ) {
+        let tex = if let Some(image) = &self.texture { 
+            let Vec2{ x: u, y: v } = inter.shape.sample(inter.local_point) * self.texture_size;
 
             image.sample(u, v)
         }
         else {
             Color::WHITE
-        };
+        } * inter.shape.color(inter.local_point);
 
-        // Construct coordinate system aligned to original normal
-        let tangent_matrix = tangent_to_world_matrix(inter.normal);
+        let normal = if let Some(map) = &self.normal_map {
+            // Construct coordinate system aligned to original normal
+            let tangent_matrix = tangent_to_world_matrix(inter.normal);
 
-        let normal = if let Some(map) = self.normal_map {
-            let Vec2 { x: u, y: v } = inter.shape.sample(inter.point) * self.texture_size;
+            let Vec2 { x: u, y: v } = inter.shape.sample(inter.local_point) * self.texture_size;
 
             let normal: Vec3 = map.sample(u, v).into();
             let normal = normal*2.0 - 1.0; // Transform normal from range [0; 1] to [-1; 1]
@@ -238,20 +302,101 @@ impl<'a> Material<'a> {
         }
         else { inter.normal };
 
+        ( tex, normal )
+    }
+
+    /// Returns the BSDF times the cosine term, for light arriving from `dir` and leaving back along `ray`
+    ///
+    /// Materials scattering in a single direction return `None`, as sampling lights can't hit that direction.
+    pub fn eval(&self, ray: &Ray, inter: &Inter<&dyn Traceable>, dir: Vec3) -> Option<Color> {
+        use MaterialKind::*;
+
+        let specular = match self.kind {
+            Lambertian { .. } => false,
+            Metal { roughness, .. } => roughness <= 0.0,
+            Transparent { .. } | Emmitive { .. } => true
+        };
+        if specular { return None }
+
+        // Light coming from behind the actual surface can't reach it
+        if dir.dot(inter.geometric_normal) <= 0.0 { return Some(Color::BLACK) }
+
+        let ( tex, normal ) = self.surface(inter);
+        let cosine_law = dir.dot(normal).max(0.0);
+
+        Some(match self.kind {
+            Lambertian { albedo } => albedo * tex * (cosine_law / PI),
+            Metal { albedo, roughness } => {
+                // Normalized Phong lobe around the mirror direction
+                let exponent = phong_exponent(roughness);
+                let alignment = dir.dot(ray.dir.reflect(normal)).max(0.0);
+
+                albedo * tex * ((exponent + 2.0) / (2.0*PI) * alignment.powf(exponent) * cosine_law)
+            },
+            Transparent { .. } | Emmitive { .. } => unreachable!()
+        })
+    }
+
+    /// Density over solid angle with which [`Material::scatter`] picks `dir`, zero for specular materials
+    pub fn pdf(&self, ray: &Ray, inter: &Inter<&dyn Traceable>, dir: Vec3) -> f32 {
+        let ( _, normal ) = self.surface(inter);
+
+        self.pdf_around(ray, normal, dir)
+    }
+
+    fn pdf_around(&self, ray: &Ray, normal: Vec3, dir: Vec3) -> f32 {
+        use MaterialKind::*;
+
+        match self.kind {
+            Lambertian { .. } => dir.dot(normal).max(0.0) / PI,
+            Metal { roughness, .. } if roughness > 0.0 => {
+                let exponent = phong_exponent(roughness);
+                let alignment = dir.dot(ray.dir.reflect(normal)).max(0.0);
+
+                (exponent + 1.0) / (2.0*PI) * alignment.powf(exponent)
+            },
+            _ => 0.0
+        }
+    }
+
+    /// Samples the direction of the next ray
+    pub fn scatter(&self, ray: &Ray, inter: &Inter<&dyn Traceable>) -> Scatter {
+        use MaterialKind::*;
+
+        let ( tex, normal ) = self.surface(inter);
+
         match self.kind {
             Lambertian { albedo } => {
-                let ray = Ray { start: inter.point, dir: random_vector_in_hemisphere(tangent_matrix) };
-                let cosine_law = ray.dir.dot(normal).max(0.0);
+                // Cosine weighted sampling cancels out the cosine law and the 1/pi of the BRDF
+                let dir = random_cosine_vector(tangent_to_world_matrix(normal));
 
-                ( Some(ray), albedo * tex * cosine_law )
+                // Interpolated normals can send rays through the actual surface, which would leak light
+                if dir.dot(inter.geometric_normal) <= 0.0 { return Scatter::absorbed() }
+
+                Scatter {
+                    ray: Some(Ray { start: inter.point, dir, time: ray.time }),
+                    attenuation: albedo * tex,
+                    pdf: Some(self.pdf_around(ray, normal, dir))
+                }
             },
-            Metal { albedo } => {
-                let reflected = ray.dir.reflect(normal);
+            Metal { albedo, roughness } => {
+                let mut reflected = ray.dir.reflect(normal);
+                let mut weight = albedo * tex;
+                let mut pdf = None;
+
+                if roughness > 0.0 {
+                    reflected = random_vector_in_lobe(reflected, roughness);
+
+                    // Ratio of the normalization factors of the lobe as a BRDF and as a density
+                    let exponent = phong_exponent(roughness);
+                    weight *= (exponent + 2.0) / (exponent + 1.0) * reflected.dot(normal).max(0.0);
+                    pdf = Some(self.pdf_around(ray, normal, reflected));
+                }
 
-                let ray = Ray { start: inter.point, dir: reflected };
-                let cosine_law = ray.dir.dot(normal).max(0.0);
+                // Perturbed reflections or reflections off interpolated normals going through the surface are absorbed
+                if reflected.dot(inter.geometric_normal) <= 0.0 { return Scatter::absorbed() }
 
-                ( Some(ray), albedo * tex * cosine_law )
+                Scatter { ray: Some(Ray { start: inter.point, dir: reflected, time: ray.time }), attenuation: weight, pdf }
             },
             Transparent { refraction_index: index } => {
                 let mu = if inter.front { 1.0 / index } else { index };
@@ -263,7 +408,7 @@ impl<'a> Material<'a> {
                     mu * sin_theta > 1.0 || // Snells law, if n1/n2 * sin(theta) > 1.0 -> Total internal reflection
                     Material::schlick_reflectance(cos_theta, mu) > random() // Randomly reflect or refract, but the steeper the angle of vision, the more reflection is choosen
                 {
-                    Ray { start: inter.point, dir: ray.dir.reflect(normal) }
+                    Ray { start: inter.point, dir: ray.dir.reflect(normal), time: ray.time }
                 }
                 else {  
                     let out_perp = mu * ( ray.dir + cos_theta*normal );
@@ -271,13 +416,13 @@ impl<'a> Material<'a> {
 
                     let refracted_dir = out_perp + out_parallel;
 
-                    Ray { start: inter.point, dir: refracted_dir.normalize() }
+                    Ray { start: inter.point, dir: refracted_dir.normalize(), time: ray.time }
                 };
 
-                ( Some(ray), Color::WHITE )
+                Scatter { ray: Some(ray), attenuation: Color::WHITE, pdf: None }
             },
-            Emmitive { color, intensity } => {
-                ( None, color * intensity )
+            Emmitive { .. } => {
+                Scatter { ray: None, attenuation: self.emitted(inter).unwrap_or(Color::BLACK), pdf: None }
             }
         }
     }
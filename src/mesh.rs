@@ -0,0 +1,134 @@
+use std::{collections::HashMap, fmt, io, path::{Path, PathBuf}};
+
+use glam::Mat4;
+use image::ImageError;
+
+use crate::{
+    shape::Triangle,
+    bvh::Bvh,
+    distribution::Distribution,
+    intersection::{Traceable, address, area_stretch},
+    obj::load_obj_file,
+    ply::load_ply_file,
+    stl::load_stl_file,
+    gltf_file::load_gltf_file
+};
+
+/// Error raised by the mesh loaders
+#[derive(Debug)]
+pub enum MeshError {
+    Io { path: PathBuf, error: io::Error },
+    /// Malformed content, `line` starts at 1
+    Parse { path: PathBuf, line: usize, message: String },
+    /// Content that is inconsistent as a whole, such as a wrong element count
+    Invalid { path: PathBuf, message: String },
+    Texture { path: PathBuf, error: ImageError },
+    Unsupported { path: PathBuf }
+}
+
+impl MeshError {
+    pub(crate) fn io(path: &Path, error: io::Error) -> Self {
+        MeshError::Io { path: path.to_owned(), error }
+    }
+
+    pub(crate) fn parse(path: &Path, line: usize, message: impl Into<String>) -> Self {
+        MeshError::Parse { path: path.to_owned(), line, message: message.into() }
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            MeshError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            MeshError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+            MeshError::Texture { path, error } => write!(f, "{}: could not load texture: {}", path.display(), error),
+            MeshError::Unsupported { path } => write!(f, "{}: unsupported mesh format", path.display())
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::Io { error, .. } => Some(error),
+            MeshError::Texture { error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
+
+/// Loads the triangles of a mesh, picking the loader from the file extension
+pub fn load_mesh<P: AsRef<Path>>(file: P) -> Result<Vec<Triangle>, MeshError> {
+    let file = file.as_ref();
+    let extension = file.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("stl") => load_stl_file(file),
+        Some("ply") => load_ply_file(file),
+        Some("obj") => Ok(load_obj_file(file)?.into_iter().flat_map(|g| g.triangles).collect()),
+        Some("gltf" | "glb") => Ok(load_gltf_file(file)?.triangles),
+        _ => Err(MeshError::Unsupported { path: file.to_owned() })
+    }
+}
+
+/// Triangles gathered under their own hierarchy, placed in scenes by [`Instance`](crate::instance::Instance)s without being copied
+#[derive(Debug)]
+pub struct Mesh {
+    bvh: Bvh<Triangle>,
+    /// Positions of the emissive triangles among the shapes of the hierarchy
+    emissive: Vec<usize>,
+    /// Picks emissive triangles in proportion to their power
+    lights: Distribution,
+    /// Index of each emissive triangle in `emissive`, by address
+    indices: HashMap<usize, usize>
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        let bvh = Bvh::build(triangles);
+
+        let emissive: Vec<usize> = bvh.shapes().enumerate().filter(|( _, t )| t.power() > 0.0).map(|( i, _ )| i).collect();
+
+        Mesh {
+            lights: Distribution::new(emissive.iter().map(|&i| bvh.shape(i).power())),
+            indices: emissive.iter().enumerate().map(|( index, &i )| ( address(bvh.shape(i)), index )).collect(),
+            emissive,
+            bvh
+        }
+    }
+
+    pub fn bvh(&self) -> &Bvh<Triangle> {
+        &self.bvh
+    }
+
+    /// Number of triangles
+    pub fn len(&self) -> usize {
+        self.bvh.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bvh.len() == 0
+    }
+
+    /// Power emitted by the triangles once placed by `to_world`, `to_local` being its inverse
+    pub(crate) fn power(&self, to_world: Mat4, to_local: Mat4) -> f32 {
+        self.emissive.iter().map(|&i| {
+            let triangle = self.bvh.shape(i);
+            triangle.power() * area_stretch(to_world, to_local, triangle.edge1.cross(triangle.edge2).normalize())
+        }).sum()
+    }
+
+    /// Picks an emissive triangle with `u`, in proportion to its power, returning it with the chances of picking it
+    pub(crate) fn sample_emissive(&self, u: f32) -> Option<( &Triangle, f32 )> {
+        if self.emissive.is_empty() { return None }
+
+        let ( index, _ ) = self.lights.sample(u);
+        Some(( self.bvh.shape(self.emissive[index]), self.lights.probability(index) ))
+    }
+
+    /// Chances of [`Mesh::sample_emissive`] picking a triangle of the mesh
+    pub(crate) fn emissive_probability(&self, triangle: &dyn Traceable) -> f32 {
+        self.indices.get(&address(triangle)).map_or(0.0, |&index| self.lights.probability(index))
+    }
+}
@@ -0,0 +1,260 @@
+//! Objects moving over time, for motion blur and animation
+
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3, Quat, Mat4, BVec3};
+
+use crate::{shape::*, intersection::{Inter, Traceable, transform_pdf}, material::{Material, Color}};
+
+/// Placement of an object, applied as scale, then rotation, then translation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform { translation: Vec3::ZERO, rotation: Quat::IDENTITY, scale: Vec3::ONE }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vec3) -> Self {
+        Transform { translation, ..Default::default() }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    /// Interpolates linearly, and spherically for the rotation
+    pub fn lerp(&self, other: &Transform, t: f32) -> Self {
+        Transform {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t)
+        }
+    }
+}
+
+/// How a keyframe goes to the next one
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    /// Constant speed, with rotations slerped
+    #[default]
+    Linear,
+    /// Cubic curve easing in and out of the first and last keyframes and smooth through the others,
+    /// with rotations slerped and eased in and out of each keyframe
+    Bezier
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub transform: Transform,
+    pub interpolation: Interpolation
+}
+
+impl Keyframe {
+    pub fn new(time: f32, transform: Transform) -> Self {
+        Keyframe { time, transform, interpolation: Interpolation::Linear }
+    }
+
+    pub fn set_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+}
+
+/// Transform varying over time, interpolated between keyframes and held before the first and after the last one
+///
+/// Rotations between consecutive keyframes take the shortest path, so full turns need keyframes less than 180° apart.
+#[derive(Debug, Clone)]
+pub struct Motion {
+    keyframes: Arc<[Keyframe]>
+}
+
+impl Motion {
+    /// Panics if `keyframes` is empty
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Self {
+        assert!(!keyframes.is_empty(), "a motion needs at least one keyframe");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Motion { keyframes: keyframes.into() }
+    }
+
+    /// Goes from `start` at time 0 to `end` at time 1
+    pub fn linear(start: Transform, end: Transform) -> Self {
+        Motion::new(vec![ Keyframe::new(0.0, start), Keyframe::new(1.0, end) ])
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn at(&self, time: f32) -> Transform {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+
+        let ( a, b ) = match ( self.keyframes.get(next.wrapping_sub(1)), self.keyframes.get(next) ) {
+            ( Some(a), Some(b) ) => ( a, b ),
+            ( Some(k), None ) | ( None, Some(k) ) => return k.transform,
+            ( None, None ) => unreachable!()
+        };
+
+        let t = (time - a.time) / (b.time - a.time);
+
+        match a.interpolation {
+            Interpolation::Linear => a.transform.lerp(&b.transform, t),
+            Interpolation::Bezier => {
+                let duration = b.time - a.time;
+
+                // Control points a third of the way along the tangents, like the automatic handles of DCC tools
+                let curve = |f: fn(&Transform) -> Vec3| {
+                    let p0 = f(&a.transform);
+                    let p3 = f(&b.transform);
+                    let p1 = p0 + self.tangent(next - 1, f) * duration / 3.0;
+                    let p2 = p3 - self.tangent(next, f) * duration / 3.0;
+
+                    let u = 1.0 - t;
+                    p0*u*u*u + p1*3.0*u*u*t + p2*3.0*u*t*t + p3*t*t*t
+                };
+
+                Transform {
+                    translation: curve(|t| t.translation),
+                    rotation: a.transform.rotation.slerp(b.transform.rotation, t*t*(3.0 - 2.0*t)),
+                    scale: curve(|t| t.scale)
+                }
+            }
+        }
+    }
+
+    /// Rate of change of a component at a keyframe, flat on the first and last keyframes to ease in and out
+    fn tangent(&self, i: usize, f: fn(&Transform) -> Vec3) -> Vec3 {
+        let k = &self.keyframes;
+
+        if i == 0 || i + 1 >= k.len() {
+            Vec3::ZERO
+        }
+        else {
+            (f(&k[i + 1].transform) - f(&k[i - 1].transform)) / (k[i + 1].time - k[i - 1].time)
+        }
+    }
+}
+
+/// Wraps a shape defined in its own space and moves it according to the time of the rays
+#[derive(Debug)]
+pub struct Moving<T> {
+    pub shape: T,
+    pub motion: Motion
+}
+
+impl<T> Moving<T> {
+    pub fn new(shape: T, motion: Motion) -> Self {
+        Moving { shape, motion }
+    }
+}
+
+/// Number of steps between keyframes when bounding shapes moving along curves
+const BOUND_STEPS: usize = 16;
+
+impl<T: Traceable> Shape for Moving<T> {
+    fn position(&self) -> Vec3 {
+        let keyframes = self.motion.keyframes();
+        let middle = (keyframes[0].time + keyframes[keyframes.len() - 1].time) / 2.0;
+
+        self.motion.at(middle).matrix().transform_point3(self.shape.position())
+    }
+
+    /// Bounds the shape over the whole motion, sampling between keyframes since rotations and bezier curves are not straight
+    fn bounding_box(&self) -> Rect {
+        let local = self.shape.bounding_box();
+        if !local.min.is_finite() || !local.max.is_finite() {
+            return Rect::infinite();
+        }
+
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), local.max, local.min))
+            .collect();
+
+        let mut bound = Rect::empty();
+
+        let keyframes = self.motion.keyframes();
+        let mut add = |transform: Transform| {
+            let matrix = transform.matrix();
+            for &corner in &corners {
+                let p = matrix.transform_point3(corner);
+                bound.min = bound.min.min(p);
+                bound.max = bound.max.max(p);
+            }
+        };
+
+        add(keyframes[0].transform);
+        for pair in keyframes.windows(2) {
+            for step in 1..=BOUND_STEPS {
+                add(self.motion.at(pair[0].time + (pair[1].time - pair[0].time) * step as f32 / BOUND_STEPS as f32));
+            }
+        }
+
+        bound
+    }
+}
+
+impl<T: Traceable> Traceable for Moving<T> {
+    fn material(&self) -> Option<&Material> {
+        self.shape.material()
+    }
+
+    fn ray_intersection(&self, ray: &Ray) -> Option<Inter<&dyn Traceable>> {
+        let to_world = self.motion.at(ray.time).matrix();
+        let to_local = to_world.inverse();
+
+        let local_ray = Ray {
+            start: to_local.transform_point3(ray.start),
+            dir: to_local.transform_vector3(ray.dir).normalize(),
+            time: ray.time
+        };
+
+        let inter = self.shape.ray_intersection(&local_ray)?;
+
+        Some(Inter { parent: Some(self), ..inter.transform(to_world, to_local) })
+    }
+
+    fn sample(&self, p: Vec3) -> Vec2 {
+        self.shape.sample(p)
+    }
+
+    fn color(&self, p: Vec3) -> Color {
+        self.shape.color(p)
+    }
+
+    /// Power of the shape in its own space, which is enough to guide sampling
+    fn power(&self) -> f32 {
+        self.shape.power()
+    }
+
+    /// Samples the shape where it is at `time`
+    fn sample_towards(&self, point: Vec3, time: f32) -> Option<( Inter<&dyn Traceable>, f32 )> {
+        let to_world = self.motion.at(time).matrix();
+        let to_local = to_world.inverse();
+
+        let from = to_local.transform_point3(point);
+        let ( local, pdf ) = self.shape.sample_towards(from, time)?;
+        let world = Inter { parent: Some(self as &dyn Traceable), ..local.clone().transform(to_world, to_local) };
+
+        let pdf = transform_pdf(pdf, from, &local, point, &world, to_world, to_local);
+        (pdf > 0.0).then_some(( world, pdf ))
+    }
+
+    fn pdf_towards(&self, point: Vec3, time: f32, inter: &Inter<&dyn Traceable>) -> f32 {
+        let to_world = self.motion.at(time).matrix();
+        let to_local = to_world.inverse();
+
+        let from = to_local.transform_point3(point);
+        let local = inter.clone().transform(to_local, to_world);
+        let pdf = self.shape.pdf_towards(from, time, &local);
+
+        transform_pdf(pdf, from, &local, point, inter, to_world, to_local)
+    }
+}
@@ -0,0 +1,353 @@
+//! Wavefront OBJ and MTL loading
+
+use std::{collections::HashMap, path::{Path, PathBuf}, str::SplitWhitespace, sync::Arc};
+
+use glam::{Vec2, Vec3};
+
+use crate::{
+    mesh::MeshError,
+    shape::{Triangle, Vertex},
+    material::{Material, Color},
+    texture::Texture
+};
+
+/// Triangles sharing a `g` or `o` statement
+#[derive(Debug)]
+pub struct ObjGroup {
+    pub name: String,
+    pub triangles: Vec<Triangle>
+}
+
+/// Parses between `min` and `N` numbers, the missing optional ones being left at 0
+fn parse_floats<const N: usize>(tokens: SplitWhitespace, min: usize, path: &Path, line: usize) -> Result<( [f32; N], usize ), MeshError> {
+    let mut out = [0.0; N];
+    let mut count = 0;
+
+    for token in tokens {
+        if count == N {
+            return Err(MeshError::parse(path, line, format!("unexpected `{}` after {} numbers", token, N)));
+        }
+        out[count] = token.parse()
+            .map_err(|_| MeshError::parse(path, line, format!("invalid number `{}`", token)))?;
+        count += 1;
+    }
+
+    if count < min {
+        let expected = if min == N { N.to_string() } else { format!("{} to {}", min, N) };
+        return Err(MeshError::parse(path, line, format!("expected {} numbers", expected)));
+    }
+
+    Ok(( out, count ))
+}
+
+/// Parses exactly `N` numbers
+fn parse_exact<const N: usize>(tokens: SplitWhitespace, path: &Path, line: usize) -> Result<[f32; N], MeshError> {
+    parse_floats::<N>(tokens, N, path, line).map(|( out, _ )| out)
+}
+
+/// Turns a 1-based, possibly negative, OBJ index into an index in a list of `len` elements
+fn resolve_index(token: &str, len: usize, path: &Path, line: usize) -> Result<usize, MeshError> {
+    let index: isize = token.parse()
+        .map_err(|_| MeshError::parse(path, line, format!("invalid index `{}`", token)))?;
+
+    let resolved = if index < 0 { len as isize + index } else { index - 1 };
+
+    if (0..len as isize).contains(&resolved) {
+        Ok(resolved as usize)
+    }
+    else {
+        Err(MeshError::parse(path, line, format!("index {} out of range", index)))
+    }
+}
+
+#[derive(Debug)]
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    emissive: Color,
+    refraction_index: Option<f32>,
+    dissolve: f32,
+    illum: u32,
+    texture: Option<Arc<Texture>>,
+    texture_size: Option<( f32, f32 )>,
+    normal_map: Option<Arc<Texture>>,
+    bump_map: Option<Arc<Texture>>
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Color::WHITE,
+            specular: Color::BLACK,
+            emissive: Color::BLACK,
+            refraction_index: None,
+            dissolve: 1.0,
+            illum: 2,
+            texture: None,
+            texture_size: None,
+            normal_map: None,
+            bump_map: None
+        }
+    }
+}
+
+fn luminance(c: Color) -> f32 {
+    0.2126*c.r + 0.7152*c.g + 0.0722*c.b
+}
+
+impl MtlMaterial {
+    /// Picks the closest material kind, since MTL describes a blend of every lobe
+    fn to_material(&self) -> Material {
+        let emission = self.emissive.r.max(self.emissive.g).max(self.emissive.b);
+
+        let mut material = if emission > 0.0 {
+            Material::new_emmitive(self.emissive / emission, emission)
+        }
+        else if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            Material::new_transparent(self.refraction_index.unwrap_or(1.5))
+        }
+        else if matches!(self.illum, 3 | 5) || luminance(self.specular) > luminance(self.diffuse) {
+            Material::new_metal(self.specular)
+        }
+        else {
+            Material::new_lambertian(self.diffuse)
+        };
+
+        if let Some(texture) = &self.texture {
+            material = material.set_texture(texture.clone());
+        }
+        // Normal maps are more precise than the normals derived from bump maps
+        if let Some(normal_map) = self.normal_map.as_ref().or(self.bump_map.as_ref()) {
+            material = material.set_normal(normal_map.clone());
+        }
+        if let Some(size) = self.texture_size {
+            material = material.set_size(size);
+        }
+
+        material
+    }
+}
+
+/// Arguments of a `map_*` statement
+struct TextureStatement {
+    file: String,
+    /// The `-s` option
+    scale: Option<( f32, f32 )>,
+    /// The `-bm` option
+    bump_multiplier: f32
+}
+
+/// Parses the arguments of a `map_*` statement
+fn parse_texture_statement(args: &str) -> TextureStatement {
+    let mut tokens = args.split_whitespace().peekable();
+    let mut scale = None;
+    let mut bump_multiplier = 1.0;
+
+    while let Some(option) = tokens.next_if(|t| t.starts_with('-')) {
+        // Maximum number of arguments taken by each option
+        let count = match option {
+            "-s" | "-o" | "-t" => 3,
+            "-mm" => 2,
+            "-blendu" | "-blendv" | "-boost" | "-texres" | "-clamp" | "-bm" | "-imfchan" | "-type" => 1,
+            _ => 0
+        };
+
+        let mut values = Vec::new();
+        for _ in 0..count {
+            match tokens.next_if(|v| count == 1 || v.parse::<f32>().is_ok()) {
+                Some(v) => values.push(v),
+                None => break
+            }
+        }
+
+        if option == "-s" {
+            let value = |i: usize| values.get(i).and_then(|v| v.parse().ok()).unwrap_or(1.0);
+            scale = Some(( value(0), value(1) ));
+        }
+        if option == "-bm" {
+            bump_multiplier = values.first().and_then(|v| v.parse().ok()).unwrap_or(1.0);
+        }
+    }
+
+    TextureStatement { file: tokens.collect::<Vec<_>>().join(" "), scale, bump_multiplier }
+}
+
+fn load_mtl_file(path: &Path, textures: &mut HashMap<PathBuf, Arc<Texture>>, materials: &mut HashMap<String, Material>) -> Result<(), MeshError> {
+    let source = std::fs::read_to_string(path).map_err(|e| MeshError::io(path, e))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut load_texture = |file: &str| -> Result<Arc<Texture>, MeshError> {
+        let file = directory.join(file.replace('\\', "/"));
+
+        if let Some(texture) = textures.get(&file) {
+            return Ok(texture.clone());
+        }
+
+        let texture = Arc::new(Texture::from_file(&file).map_err(|error| MeshError::Texture { path: file.clone(), error })?);
+        textures.insert(file, texture.clone());
+
+        Ok(texture)
+    };
+
+    let mut current: Option<( String, MtlMaterial )> = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let line = line.split('#').next().unwrap().trim();
+
+        let Some(keyword) = line.split_whitespace().next() else { continue };
+        let args = line[keyword.len()..].trim();
+
+        if keyword == "newmtl" {
+            if let Some(( name, material )) = current.take() {
+                materials.insert(name, material.to_material());
+            }
+            current = Some(( args.to_owned(), MtlMaterial::default() ));
+            continue;
+        }
+
+        let Some(( _, material )) = current.as_mut() else {
+            return Err(MeshError::parse(path, number, format!("`{}` before any `newmtl`", keyword)));
+        };
+
+        let color = |args: &str| -> Result<Color, MeshError> {
+            match parse_floats::<3>(args.split_whitespace(), 1, path, number)? {
+                // A single value sets every channel
+                ( [v, _, _], 1 ) => Ok(Color::splat(v)),
+                ( [r, g, b], 3 ) => Ok(Color::new(r, g, b)),
+                _ => Err(MeshError::parse(path, number, "expected 1 or 3 numbers"))
+            }
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = color(args)?,
+            "Ks" => material.specular = color(args)?,
+            "Ke" => material.emissive = color(args)?,
+            "Ni" => material.refraction_index = Some(parse_exact::<1>(args.split_whitespace(), path, number)?[0]),
+            "d" => material.dissolve = parse_exact::<1>(args.split_whitespace(), path, number)?[0],
+            "Tr" => material.dissolve = 1.0 - parse_exact::<1>(args.split_whitespace(), path, number)?[0],
+            "illum" => material.illum = args.parse()
+                .map_err(|_| MeshError::parse(path, number, format!("invalid illumination model `{}`", args)))?,
+            "map_Kd" => {
+                let statement = parse_texture_statement(args);
+                material.texture = Some(load_texture(&statement.file)?);
+                material.texture_size = statement.scale.or(material.texture_size);
+            },
+            "norm" => {
+                let statement = parse_texture_statement(args);
+                material.normal_map = Some(load_texture(&statement.file)?);
+                material.texture_size = statement.scale.or(material.texture_size);
+            },
+            "map_Bump" | "map_bump" | "bump" => {
+                let statement = parse_texture_statement(args);
+                let heights = load_texture(&statement.file)?;
+                material.bump_map = Some(Arc::new(Texture::from_height_map(&heights.data, statement.bump_multiplier)));
+                material.texture_size = statement.scale.or(material.texture_size);
+            },
+            _ => {} // Ka, Ns, Tf and the other maps have no equivalent
+        }
+    }
+
+    if let Some(( name, material )) = current {
+        materials.insert(name, material.to_material());
+    }
+
+    Ok(())
+}
+
+/// Loads an OBJ file and the MTL libraries it references
+///
+/// Polygons are triangulated as fans, vertices without normals get the normal of their face.
+pub fn load_obj_file<P: AsRef<Path>>(file: P) -> Result<Vec<ObjGroup>, MeshError> {
+    let path = file.as_ref();
+    let source = std::fs::read_to_string(path).map_err(|e| MeshError::io(path, e))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
+
+    let mut textures = HashMap::new();
+    let mut materials = HashMap::new();
+    let mut material = Material::default();
+
+    let mut groups = vec![ ObjGroup { name: String::new(), triangles: Vec::new() } ];
+
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let line = line.split('#').next().unwrap().trim();
+        let mut tokens = line.split_whitespace();
+
+        let Some(keyword) = tokens.next() else { continue };
+
+        match keyword {
+            // The optional w of positions and of texture coordinates is ignored
+            "v" => positions.push(Vec3::from_slice(&parse_floats::<4>(tokens, 3, path, number)?.0)),
+            "vn" => normals.push(Vec3::from_array(parse_exact::<3>(tokens, path, number)?).normalize_or_zero()),
+            "vt" => tex_coords.push(Vec2::from_slice(&parse_floats::<3>(tokens, 2, path, number)?.0)),
+            "f" => {
+                let vertices = tokens.map(|token| {
+                    let mut indices = token.split('/');
+
+                    let pos = resolve_index(indices.next().unwrap(), positions.len(), path, number)?;
+                    let tex = match indices.next() {
+                        Some("") | None => None,
+                        Some(i) => Some(resolve_index(i, tex_coords.len(), path, number)?)
+                    };
+                    let normal = match indices.next() {
+                        Some("") | None => None,
+                        Some(i) => Some(resolve_index(i, normals.len(), path, number)?)
+                    };
+
+                    Ok(( pos, tex, normal ))
+                }).collect::<Result<Vec<_>, MeshError>>()?;
+
+                if vertices.len() < 3 {
+                    return Err(MeshError::parse(path, number, "face with less than three vertices"));
+                }
+
+                let vertex = |( pos, tex, normal ): ( usize, Option<usize>, Option<usize> ), face_normal: Vec3| Vertex {
+                    pos: positions[pos],
+                    normal: normal.map_or(face_normal, |n| normals[n]),
+                    tex: tex.map_or(Vec2::ZERO, |t| tex_coords[t])
+                };
+
+                let triangles = &mut groups.last_mut().unwrap().triangles;
+                for i in 1..vertices.len() - 1 {
+                    let ( a, b, c ) = ( vertices[0], vertices[i], vertices[i + 1] );
+                    let face_normal = (positions[b.0] - positions[a.0]).cross(positions[c.0] - positions[a.0]).normalize_or_zero();
+
+                    triangles.push(Triangle::new(vertex(a, face_normal), vertex(b, face_normal), vertex(c, face_normal), material.clone()));
+                }
+            },
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+
+                // Reuse the current group if nothing was added to it yet
+                let last = groups.last_mut().unwrap();
+                if last.triangles.is_empty() {
+                    last.name = name;
+                }
+                else {
+                    groups.push(ObjGroup { name, triangles: Vec::new() });
+                }
+            },
+            "mtllib" => {
+                for library in tokens {
+                    load_mtl_file(&directory.join(library), &mut textures, &mut materials)?;
+                }
+            },
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+
+                material = materials.get(&name).cloned()
+                    .ok_or_else(|| MeshError::parse(path, number, format!("unknown material `{}`", name)))?;
+            },
+            _ => {} // Smoothing groups, lines, curves...
+        }
+    }
+
+    groups.retain(|g| !g.triangles.is_empty());
+
+    Ok(groups)
+}
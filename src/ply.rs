@@ -0,0 +1,276 @@
+//! PLY loading, in ASCII and binary little/big endian formats
+
+use std::path::Path;
+
+use glam::{Vec2, Vec3};
+
+use crate::{shape::{Triangle, Vertex}, material::{Material, Color}, mesh::MeshError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ScalarType {
+    I8, U8, I16, U16, I32, U32, F32, F64
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        use ScalarType::*;
+
+        Some(match name {
+            "char" | "int8" => I8,
+            "uchar" | "uint8" => U8,
+            "short" | "int16" => I16,
+            "ushort" | "uint16" => U16,
+            "int" | "int32" => I32,
+            "uint" | "uint32" => U32,
+            "float" | "float32" => F32,
+            "double" | "float64" => F64,
+            _ => return None
+        })
+    }
+
+    fn size(self) -> usize {
+        use ScalarType::*;
+
+        match self {
+            I8 | U8 => 1,
+            I16 | U16 => 2,
+            I32 | U32 | F32 => 4,
+            F64 => 8
+        }
+    }
+
+    /// Scale mapping integer colors to 0..1
+    fn color_scale(self) -> f64 {
+        use ScalarType::*;
+
+        match self {
+            U8 | I8 => 255.0,
+            U16 | I16 => 65535.0,
+            _ => 1.0
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar { name: String, ty: ScalarType },
+    List { name: String, count: ScalarType, item: ScalarType }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+/// Reads the values of the body, either from whitespace separated text or from bytes
+struct Reader<'a> {
+    path: &'a Path,
+    format: Format,
+    data: &'a [u8],
+    offset: usize,
+    line: usize
+}
+
+impl Reader<'_> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, MeshError> {
+        if self.format == Format::Ascii {
+            return self.read_token();
+        }
+
+        let size = ty.size();
+        let Some(bytes) = self.data.get(self.offset..self.offset + size) else {
+            return Err(MeshError::Invalid { path: self.path.to_owned(), message: "unexpected end of file".to_owned() });
+        };
+        self.offset += size;
+
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+
+        use ScalarType::*;
+        Ok(match ty {
+            I8 => i8::from_le_bytes(buffer[..1].try_into().unwrap()) as f64,
+            U8 => u8::from_le_bytes(buffer[..1].try_into().unwrap()) as f64,
+            I16 => i16::from_le_bytes(buffer[..2].try_into().unwrap()) as f64,
+            U16 => u16::from_le_bytes(buffer[..2].try_into().unwrap()) as f64,
+            I32 => i32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            U32 => u32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            F32 => f32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            F64 => f64::from_le_bytes(buffer[..8].try_into().unwrap())
+        })
+    }
+
+    fn read_token(&mut self) -> Result<f64, MeshError> {
+        loop {
+            match self.data.get(self.offset) {
+                Some(b'\n') => { self.line += 1; self.offset += 1; },
+                Some(c) if c.is_ascii_whitespace() => self.offset += 1,
+                Some(_) => break,
+                None => return Err(MeshError::Invalid { path: self.path.to_owned(), message: "unexpected end of file".to_owned() })
+            }
+        }
+
+        let start = self.offset;
+        while self.data.get(self.offset).is_some_and(|c| !c.is_ascii_whitespace()) {
+            self.offset += 1;
+        }
+
+        let token = String::from_utf8_lossy(&self.data[start..self.offset]);
+        token.parse().map_err(|_| MeshError::parse(self.path, self.line, format!("invalid number `{}`", token)))
+    }
+}
+
+/// Loads a PLY file, using the `red`, `green` and `blue` vertex properties as albedo when present
+pub fn load_ply_file<P: AsRef<Path>>(file: P) -> Result<Vec<Triangle>, MeshError> {
+    let path = file.as_ref();
+    let data = std::fs::read(path).map_err(|e| MeshError::io(path, e))?;
+
+    // The header is always ASCII and ends with its own line
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+    let mut line = 0;
+
+    loop {
+        let Some(end) = data[offset..].iter().position(|&c| c == b'\n') else {
+            return Err(MeshError::Invalid { path: path.to_owned(), message: "missing `end_header`".to_owned() });
+        };
+
+        let text = String::from_utf8_lossy(&data[offset..offset + end]);
+        offset += end + 1;
+        line += 1;
+
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            ["ply"] if line == 1 => {},
+            _ if line == 1 => return Err(MeshError::parse(path, line, "not a PLY file")),
+            ["format", f, _] => format = Some(match *f {
+                "ascii" => Format::Ascii,
+                "binary_little_endian" => Format::BinaryLittleEndian,
+                "binary_big_endian" => Format::BinaryBigEndian,
+                _ => return Err(MeshError::parse(path, line, format!("unknown format `{}`", f)))
+            }),
+            ["comment", ..] | ["obj_info", ..] | [] => {},
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| MeshError::parse(path, line, format!("invalid element count `{}`", count)))?,
+                properties: Vec::new()
+            }),
+            ["property", rest @ ..] => {
+                let Some(element) = elements.last_mut() else {
+                    return Err(MeshError::parse(path, line, "property before any element"));
+                };
+                let ty = |name: &str| ScalarType::parse(name)
+                    .ok_or_else(|| MeshError::parse(path, line, format!("unknown type `{}`", name)));
+
+                element.properties.push(match rest {
+                    ["list", count, item, name] => Property::List { name: name.to_string(), count: ty(count)?, item: ty(item)? },
+                    [t, name] => Property::Scalar { name: name.to_string(), ty: ty(t)? },
+                    _ => return Err(MeshError::parse(path, line, "malformed property"))
+                });
+            },
+            ["end_header"] => break,
+            _ => return Err(MeshError::parse(path, line, format!("unexpected `{}`", text.trim())))
+        }
+    }
+
+    let Some(format) = format else {
+        return Err(MeshError::Invalid { path: path.to_owned(), message: "missing `format` in header".to_owned() });
+    };
+
+    let mut reader = Reader { path, format, data: &data, offset, line: line + 1 };
+
+    let mut vertices = Vec::new();
+    let mut colors = Vec::new();
+    let mut faces = Vec::new();
+
+    for element in &elements {
+        let index = |names: &[&str]| element.properties.iter().position(|p| matches!(p, Property::Scalar { name, .. } if names.contains(&name.as_str())));
+
+        let position = [ index(&["x"]), index(&["y"]), index(&["z"]) ];
+        let normal = [ index(&["nx"]), index(&["ny"]), index(&["nz"]) ];
+        let tex = [ index(&["u", "s", "texture_u", "texture_s"]), index(&["v", "t", "texture_v", "texture_t"]) ];
+        let color = [ index(&["red", "r", "diffuse_red"]), index(&["green", "g", "diffuse_green"]), index(&["blue", "b", "diffuse_blue"]) ];
+
+        let face_list = element.properties.iter()
+            .position(|p| matches!(p, Property::List { name, .. } if name == "vertex_indices" || name == "vertex_index"));
+
+        let mut values = vec![0.0; element.properties.len()];
+
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar { ty, .. } => values[i] = reader.read(*ty)?,
+                    Property::List { count, item, .. } => {
+                        let count = reader.read(*count)? as usize;
+                        let items = (0..count).map(|_| reader.read(*item).map(|v| v as usize)).collect::<Result<Vec<_>, _>>()?;
+
+                        if Some(i) == face_list && element.name == "face" {
+                            faces.push(items);
+                        }
+                    }
+                }
+            }
+
+            if element.name != "vertex" { continue }
+
+            let get = |i: Option<usize>| i.map(|i| values[i] as f32);
+            let vec3 = |[x, y, z]: [Option<usize>; 3]| Some(Vec3::new(get(x)?, get(y)?, get(z)?));
+
+            vertices.push(Vertex {
+                pos: vec3(position).unwrap_or(Vec3::ZERO),
+                normal: vec3(normal).map_or(Vec3::ZERO, Vec3::normalize_or_zero),
+                tex: Vec2::new(get(tex[0]).unwrap_or(0.0), get(tex[1]).unwrap_or(0.0))
+            });
+
+            if let [Some(r), Some(g), Some(b)] = color {
+                let scale = |i: usize| match element.properties[i] {
+                    Property::Scalar { ty, .. } => (values[i] / ty.color_scale()) as f32,
+                    Property::List { .. } => unreachable!()
+                };
+                colors.push(Color::new(scale(r), scale(g), scale(b)));
+            }
+        }
+    }
+
+    let mut triangles = Vec::new();
+
+    for face in faces {
+        if face.len() < 3 { continue }
+        if let Some(&i) = face.iter().find(|&&i| i >= vertices.len()) {
+            return Err(MeshError::Invalid { path: path.to_owned(), message: format!("face references vertex {} out of {}", i, vertices.len()) });
+        }
+
+        // Triangulate polygons as fans
+        for i in 1..face.len() - 1 {
+            let indices = [ face[0], face[i], face[i + 1] ];
+            let [mut a, mut b, mut c] = indices.map(|i| vertices[i]);
+
+            let face_normal = (b.pos - a.pos).cross(c.pos - a.pos).normalize_or_zero();
+            for v in [ &mut a, &mut b, &mut c ] {
+                if v.normal == Vec3::ZERO { v.normal = face_normal }
+            }
+
+            let mut triangle = Triangle::new(a, b, c, Material::new_lambertian(Color::WHITE));
+            if !colors.is_empty() {
+                triangle = triangle.set_vertex_colors(indices.map(|i| colors[i]));
+            }
+
+            triangles.push(triangle);
+        }
+    }
+
+    Ok(triangles)
+}
@@ -0,0 +1,401 @@
+use std::{collections::HashMap, fmt, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
+
+use glam::Vec3;
+use image::{RgbImage, Rgb, Pixel};
+use rayon::prelude::*;
+
+use crate::{
+    bvh::Bvh,
+    camera::{Camera, pixel_as_ray},
+    intersection::{Inter, Traceable, address},
+    distribution::Distribution,
+    light::{Light, LightSample},
+    material::Color,
+    rng::{self, random},
+    scene::Scene,
+    shape::Ray,
+    sky::Sky,
+    stereo::StereoRig
+};
+
+/// Emissive shapes of the scene, sampled as a single light by picking them in proportion to the power they emit
+struct Emitters<'a> {
+    shapes: Vec<&'a dyn Traceable>,
+    distribution: Distribution,
+    /// Index of each shape, by address
+    indices: HashMap<usize, usize>
+}
+
+impl<'a> Emitters<'a> {
+    fn new(shapes: &'a [Box<dyn Traceable>]) -> Self {
+        let shapes: Vec<&dyn Traceable> = shapes.iter().map(Box::as_ref).filter(|shape| shape.power() > 0.0).collect();
+
+        Emitters {
+            distribution: Distribution::new(shapes.iter().map(|shape| shape.power())),
+            indices: shapes.iter().enumerate().map(|(i, &shape)| ( address(shape), i )).collect(),
+            shapes
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+
+    fn sample(&self, point: Vec3, time: f32) -> Option<LightSample> {
+        let ( i, _ ) = self.distribution.sample(random());
+        let shape = self.shapes[i];
+
+        let ( inter, pdf ) = shape.sample_towards(point, time)?;
+        let distance = inter.point.distance(point);
+
+        Some(LightSample {
+            dir: (inter.point - point) / distance,
+            distance,
+            radiance: inter.shape.material()?.emitted(&inter)?,
+            pdf: Some(pdf * self.distribution.probability(i))
+        })
+    }
+
+    /// Density of [`Emitters::sample`] for a ray from `point` at `time` hitting an emissive shape, zero for shapes that aren't sampled
+    fn pdf(&self, point: Vec3, time: f32, inter: &Inter<&dyn Traceable>) -> f32 {
+        // Shapes found within others, such as the triangles of instances, are sampled through them
+        let shape = inter.parent.unwrap_or(inter.shape);
+
+        match self.indices.get(&address(shape)) {
+            Some(&i) => shape.pdf_towards(point, time, inter) * self.distribution.probability(i),
+            None => 0.0
+        }
+    }
+}
+
+/// Everything lighting a scene, shared by the paths of a render
+struct Lighting<'a> {
+    lights: Vec<Light>,
+    emitters: Emitters<'a>,
+    /// Seen by rays leaving the scene, `None` when an environment map replaces it
+    sky: Option<&'a Sky>
+}
+
+impl<'a> Lighting<'a> {
+    fn new(scene: &'a Scene) -> Self {
+        // Environment maps replace the sun and sky, and a set sun is left out rather than sampled for nothing
+        let ( sky, background ) = match &scene.environment {
+            Some(map) => ( None, Some(Light::Environment(map.clone())) ),
+            None => {
+                let sun = scene.sky.sun();
+                ( Some(&scene.sky), (sun.direction.y > 0.0).then_some(Light::Sun(sun)) )
+            }
+        };
+
+        Lighting {
+            lights: background.into_iter().chain(scene.lights.iter().cloned()).collect(),
+            emitters: Emitters::new(scene.shapes()),
+            sky
+        }
+    }
+
+    /// Number of choices of `sample_light`, each light and all the emissive shapes together
+    fn choices(&self) -> usize {
+        self.lights.len() + usize::from(!self.emitters.is_empty())
+    }
+
+    /// Weight of a light found by a scattered ray, against the chances of `sample_light` having picked the same direction
+    fn weight(&self, light_pdf: f32, scatter_pdf: Option<f32>) -> f32 {
+        scatter_pdf.map_or(1.0, |pdf| power_heuristic(pdf, light_pdf / self.choices() as f32))
+    }
+}
+
+/// Weight of a sample among two sampling strategies, from the densities of both
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let ( a, b ) = ( pdf*pdf, other*other );
+
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+/// Uniformly picks a light, or the emissive shapes, and samples it from a point, returning the light it scatters back along `ray`
+///
+/// Specular materials give black, their reflection has to find the lights on its own.
+/// At the last bounce, where scattered rays are not followed, the sample is the only way to the lights and counts fully.
+fn sample_light(scene: Option<&Bvh<&dyn Traceable>>, lighting: &Lighting, ray: &Ray, inter: &Inter<&dyn Traceable>, last: bool) -> Color {
+    let choices = lighting.choices();
+    if choices == 0 { return Color::BLACK }
+
+    let Some(material) = inter.shape.material() else { return Color::BLACK };
+    let choice = ((random::<f32>() * choices as f32) as usize).min(choices - 1);
+
+    let sample = match lighting.lights.get(choice) {
+        Some(light) => light.sample(inter.point),
+        None => lighting.emitters.sample(inter.point, ray.time)
+    };
+    let Some(sample) = sample else { return Color::BLACK };
+    let Some(bsdf) = material.eval(ray, inter, sample.dir) else { return Color::BLACK };
+
+    // Lights are not part of the scene and emissive shapes are hit right at the sampled point,
+    // so only what is in front of them can cast a shadow, up to a distance measured from the offset start of the shadow ray
+    let shadow = Ray { start: inter.point, dir: sample.dir, time: ray.time }.offset();
+    let reach = sample.distance * 0.999 - shadow.start.distance(inter.point);
+    if scene.is_some_and(|bvh| bvh.occluded(&shadow, reach)) {
+        return Color::BLACK;
+    }
+
+    match sample.pdf {
+        // Point and spot lights cannot be found by scattered rays
+        None => bsdf * sample.radiance * choices as f32,
+        Some(pdf) => {
+            let pdf = pdf / choices as f32;
+
+            // The scattered ray could have found the light as well, see `trace`
+            let weight = if last { 1.0 } else { power_heuristic(pdf, material.pdf(ray, inter, sample.dir)) };
+
+            bsdf * sample.radiance * (weight / pdf)
+        }
+    }
+}
+
+/// Follows a path, `scatter_pdf` being the density with which the previous bounce picked the direction of the ray
+///
+/// Lights are both sampled at each bounce and found by scattered rays, the two strategies being combined with multiple importance sampling.
+/// Camera rays and specular bounces have no density, and count lights fully.
+fn trace(scene: Option<&Bvh<&dyn Traceable>>, lighting: &Lighting, ray: Ray, count: u32, max_depth: u32, scatter_pdf: Option<f32>) -> Color {
+    if count >= max_depth { return Color::BLACK }
+
+    let hit = scene.and_then(|bvh| bvh.intersects(&ray));
+    let hit_distance = hit.as_ref().map_or(f32::INFINITY, |inter| inter.point.distance(ray.start));
+
+    // Area lights are opaque and end the path when they come before the scene
+    let light_hit = lighting.lights.iter()
+        .filter_map(|light| light.intersect(&ray).map(|hit| ( light, hit )))
+        .filter(|( _, hit )| hit.distance < hit_distance)
+        .min_by(|( _, a ), ( _, b )| a.distance.total_cmp(&b.distance));
+
+    if let Some(( light, light_hit )) = light_hit {
+        return light_hit.radiance * lighting.weight(light.pdf(ray.start, ray.dir, light_hit.distance), scatter_pdf);
+    }
+
+    if let Some(inter) = hit {
+        // Intersections report the shape they found within aggregates, which has a material
+        let Some(material) = inter.shape.material() else { return Color::BLACK };
+
+        if let Some(emitted) = material.emitted(&inter) {
+            return emitted * lighting.weight(lighting.emitters.pdf(ray.start, ray.time, &inter), scatter_pdf);
+        }
+
+        // Next event estimation, the light reaching the point straight from a light
+        let direct = sample_light(scene, lighting, &ray, &inter, count + 1 >= max_depth);
+
+        let scatter = material.scatter(&ray, &inter);
+
+        let indirect = if let Some(scattered) = scatter.ray {
+            trace(scene, lighting, scattered.offset(), count + 1, max_depth, scatter.pdf) * scatter.attenuation
+        }
+        else {
+            scatter.attenuation
+        };
+
+        direct + indirect
+    }
+    else {
+        // The sky is not sampled, unlike the sun and environment maps
+        let background = lighting.sky.map_or(Color::BLACK, |sky| sky.radiance(ray.dir));
+
+        lighting.lights.iter().fold(background, |color, light| match light {
+            Light::Sun(sun) if sun.contains(ray.dir) => color + sun.radiance * lighting.weight(light.pdf(ray.start, ray.dir, f32::INFINITY), scatter_pdf),
+            Light::Environment(map) => color + map.radiance(ray.dir) * lighting.weight(light.pdf(ray.start, ray.dir, f32::INFINITY), scatter_pdf),
+            _ => color
+        })
+    }
+}
+
+fn aces(x: Color) -> Color {
+    let a = 2.51f32;
+    let b = 0.03f32;
+    let c = 2.43f32;
+    let d = 0.59f32;
+    let e = 0.14f32;
+
+    (x*(x*a + b))/(x*(x*c + d) + e)
+}
+
+/// A scene ready to be rendered, its acceleration structure and lights being built once for every frame and view
+pub struct PreparedScene<'a> {
+    scene: &'a Scene,
+    bvh: Option<Bvh<&'a dyn Traceable>>,
+    lighting: Lighting<'a>
+}
+
+impl<'a> PreparedScene<'a> {
+    pub fn new(scene: &'a Scene) -> Self {
+        let shapes = scene.shapes();
+
+        PreparedScene {
+            scene,
+            bvh: (!shapes.is_empty()).then(|| Bvh::build(shapes.iter().map(Box::as_ref).collect())),
+            lighting: Lighting::new(scene)
+        }
+    }
+
+    pub fn scene(&self) -> &'a Scene {
+        self.scene
+    }
+}
+
+impl fmt::Debug for PreparedScene<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreparedScene")
+            .field("shapes", &self.scene.shapes().len())
+            .field("lights", &self.lighting.lights.len())
+            .field("emitters", &self.lighting.emitters.shapes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Owns the render settings and turns a [`Scene`] into an image
+#[derive(Clone)]
+pub struct Renderer {
+    pub width: u32,
+    pub height: u32,
+    /// Number of samples per pixel
+    pub samples: u32,
+    /// Maximum number of bounces of a ray
+    pub max_depth: u32,
+    /// Makes renders reproducible when set
+    pub seed: Option<u64>,
+    /// Runs the render instead of rayon's global pool when set
+    pool: Option<Arc<rayon::ThreadPool>>,
+    /// Called with the fraction of the image done, every tenth of it
+    progress: Option<Arc<dyn Fn(f32) + Send + Sync>>
+}
+
+impl fmt::Debug for Renderer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Renderer")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("samples", &self.samples)
+            .field("max_depth", &self.max_depth)
+            .field("seed", &self.seed)
+            .field("threads", &self.pool.as_ref().map(|pool| pool.current_num_threads()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Renderer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Renderer {
+            width,
+            height,
+            samples: 2048,
+            max_depth: 7,
+            seed: None,
+            pool: None,
+            progress: None
+        }
+    }
+
+    pub fn set_samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+    pub fn set_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+    /// Renders with a pool of its own of `threads` worker threads, built once here
+    pub fn set_threads(mut self, threads: usize) -> Result<Self, rayon::ThreadPoolBuildError> {
+        self.pool = Some(Arc::new(rayon::ThreadPoolBuilder::new().num_threads(threads).build()?));
+        Ok(self)
+    }
+    pub fn set_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+    /// Reports the progress of each image, from any of the worker threads
+    pub fn set_progress(mut self, progress: impl Fn(f32) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Renders the scene, returning a tonemapped and gamma corrected image
+    ///
+    /// Scenes rendered more than once, such as the frames of an animation, are better prepared once with [`PreparedScene::new`].
+    pub fn render(&self, scene: &Scene) -> RgbImage {
+        self.render_frame(&PreparedScene::new(scene), 0.0)
+    }
+
+    /// Renders the scene as it is at `time`, the camera shutter being relative to it
+    pub fn render_frame(&self, scene: &PreparedScene, time: f32) -> RgbImage {
+        self.render_views(scene, &[ &scene.scene.camera ], time).remove(0)
+    }
+
+    /// Renders the left and right eyes of a stereo pair, each at the size of the renderer
+    pub fn render_stereo(&self, scene: &PreparedScene, rig: &StereoRig, time: f32) -> [RgbImage; 2] {
+        let [left, right] = rig.eyes(&scene.scene.camera);
+
+        let mut views = self.render_views(scene, &[ &left, &right ], time);
+        let right = views.pop().unwrap();
+
+        [ views.pop().unwrap(), right ]
+    }
+
+    /// Renders the scene from several cameras
+    fn render_views(&self, scene: &PreparedScene, cameras: &[&Camera], time: f32) -> Vec<RgbImage> {
+        let render = || cameras.iter().map(|camera| self.render_pixels(scene, camera, time)).collect();
+
+        match &self.pool {
+            Some(pool) => pool.install(render),
+            None => render()
+        }
+    }
+
+    fn render_pixels(&self, scene: &PreparedScene, camera: &Camera, time: f32) -> RgbImage {
+        let ( bvh, lighting ) = ( scene.bvh.as_ref(), &scene.lighting );
+
+        let mut canvas = RgbImage::new(self.width, self.height);
+
+        let rows_done = AtomicUsize::new(0);
+        let rows_fraction = (self.height as usize / 10).max(1);
+
+        canvas.par_chunks_mut(self.width as usize * 3).enumerate().for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                let ( x, y ) = ( x as u32, y as u32 );
+
+                if let Some(seed) = self.seed {
+                    rng::seed_pixel(seed, x, y);
+                }
+
+                let mut color = Color::BLACK;
+
+                for _ in 0..self.samples {
+                    // Random direction through pixel for antialiasing
+                    let ray = pixel_as_ray(self.width, self.height, camera, x as f32 + random::<f32>(), y as f32 + random::<f32>(), time);
+
+                    if let Some(ray) = ray {
+                        color += trace(bvh, lighting, ray, 0, self.max_depth, None);
+                    }
+                }
+
+                color /= self.samples as f32;
+
+                color = aces(color);
+
+                color.r = color.r.min(1.0);
+                color.g = color.g.min(1.0);
+                color.b = color.b.min(1.0);
+
+                *Rgb::from_slice_mut(pixel) = color.into();
+            }
+
+            let done = rows_done.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some(progress) = &self.progress {
+                if done.is_multiple_of(rows_fraction) || done == self.height as usize {
+                    progress(done as f32 / self.height as f32);
+                }
+            }
+        });
+
+        // Gamma correction
+        canvas.iter_mut().for_each(|p| *p = (((*p as f64) / 256.0).sqrt() * 256.0) as u8 );
+
+        canvas
+    }
+}
@@ -0,0 +1,23 @@
+use std::cell::RefCell;
+
+use rand::{rngs::SmallRng, SeedableRng, Rng, distributions::{Distribution, Standard}};
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// Reseeds the random number generator of the current thread
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/// Reseeds the current thread from a base seed and a pixel, so that each pixel gets the same samples regardless of scheduling
+pub fn seed_pixel(base: u64, x: u32, y: u32) {
+    seed(base ^ ((x as u64) << 32 | y as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+}
+
+/// Drop-in replacement for `rand::random` drawing from the seedable thread-local generator
+pub fn random<T>() -> T
+where Standard: Distribution<T> {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
@@ -0,0 +1,84 @@
+use std::{path::Path, sync::Arc};
+
+use glam::Vec3;
+
+use crate::{camera::Camera, intersection::Traceable, light::Light, sky::Sky, environment::EnvironmentMap, scene_file::{self, SceneError}};
+
+/// The objects to render, along with the camera looking at them and the sky and lights lighting them
+///
+/// ```no_run
+/// use raytracing::{Scene, Camera, Color, Material, shape::Sphere};
+/// use glam::Vec3;
+///
+/// let scene = Scene::new(Camera::look_at(Vec3::new(0.0, 5.0, -20.0), Vec3::new(0.0, 5.0, 0.0), Vec3::Y))
+///     .set_light_direction(Vec3::new(-1.0, 1.0, -1.0))
+///     .add_shape(Sphere { pos: Vec3::new(0.0, 5.0, 0.0), radius: 5.0, material: Material::new_lambertian(Color::RED) });
+/// ```
+#[derive(Debug)]
+pub struct Scene {
+    pub camera: Camera,
+    /// Sun and sky, lighting the scene from every direction nothing else is seen in
+    pub sky: Sky,
+    /// Replaces the sky when set
+    pub environment: Option<Arc<EnvironmentMap>>,
+    /// Lights sampled along with the sun
+    pub lights: Vec<Light>,
+    shapes: Vec<Box<dyn Traceable>>
+}
+
+impl Scene {
+    pub fn new(camera: Camera) -> Self {
+        Scene {
+            camera,
+            sky: Sky::new(Vec3::new(-1.0, 1.0, -1.0)),
+            environment: None,
+            lights: Vec::new(),
+            shapes: Vec::new()
+        }
+    }
+
+    /// Loads a scene from a TOML file, see [`scene_file`] for the format
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        scene_file::load_scene(path)
+    }
+
+    /// Moves the sun, keeping the other parameters of the sky
+    pub fn set_light_direction(mut self, direction: Vec3) -> Self {
+        self.sky = self.sky.set_sun_direction(direction);
+        self
+    }
+
+    pub fn set_sky(mut self, sky: Sky) -> Self {
+        self.sky = sky;
+        self
+    }
+
+    pub fn set_environment(mut self, environment: EnvironmentMap) -> Self {
+        self.environment = Some(Arc::new(environment));
+        self
+    }
+
+    pub fn add_light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn add_shape<T: Traceable + 'static>(mut self, shape: T) -> Self {
+        self.push(shape);
+        self
+    }
+
+    pub fn push<T: Traceable + 'static>(&mut self, shape: T) {
+        self.shapes.push(Box::new(shape));
+    }
+
+    pub fn shapes(&self) -> &[Box<dyn Traceable>] {
+        &self.shapes
+    }
+}
+
+impl<T: Traceable + 'static> Extend<T> for Scene {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.shapes.extend(iter.into_iter().map(|s| Box::new(s) as Box<dyn Traceable>));
+    }
+}
@@ -0,0 +1,848 @@
+//! Declarative scene description loaded from a TOML file
+//!
+//! ```toml
+//! [camera]
+//! position = [20.0, 20.0, -30.0]
+//! rotation = [11.5, 0.0, 0.0] # Euler angles in degrees, applied in Y, X, Z order, or `look_at = [x, y, z]` with an optional `up`
+//! fov = 90.0 # Vertical, in degrees. Also `horizontal_fov`, or `focal_length` with `sensor_width` in millimeters
+//! aperture = 0.5 # Lens radius for depth of field, 0 for a pinhole
+//! focus_distance = 40.0
+//! aperture_blades = 6 # Polygonal aperture, round when absent
+//! # projection = { kind = "orthographic", height = 40.0 }, or "equirectangular", or "fisheye" with a fov in degrees
+//! # from = "scene.gltf" # Placement and fov of a glTF camera, the first one unless picked by `name`, overridden by the fields above
+//!
+//! [sky]
+//! elevation = 35.0 # Height of the sun above the horizon in degrees, or `direction = [x, y, z]` pointing towards it
+//! azimuth = 225.0 # Clockwise from +Z when looking down
+//! turbidity = 3.0 # From 2 for a very clear sky to 10 for a hazy one
+//! ground_albedo = [0.3, 0.3, 0.3]
+//! sun_size = 0.53 # Angular diameter in degrees
+//!
+//! # [environment] # Replaces the sky with an equirectangular Radiance HDR or OpenEXR panorama
+//! # path = "sky.hdr"
+//! # rotation = 90.0 # Around the vertical axis, in degrees
+//! # intensity = 1.0
+//!
+//! [textures.grid]
+//! path = "textures/metal.png" # Relative to the scene file
+//! wrapping = "repeat"
+//!
+//! [materials.ground]
+//! kind = "lambertian"
+//! albedo = [0.6, 0.2, 0.0]
+//! normal_map = "grid"
+//! texture_size = [0.05, 0.05]
+//!
+//! [materials.lamp]
+//! kind = "emmitive"
+//! color = [1.0, 0.9, 0.8]
+//! intensity = 4.0
+//! two_sided = false # Only lights the side the normal faces, true by default
+//!
+//! [[plane]]
+//! position = [0.0, 0.0, 0.0]
+//! normal = [0.0, 1.0, 0.0]
+//! material = "ground"
+//! ```
+//!
+//! Objects are declared with `[[sphere]]`, `[[plane]]`, `[[triangle]]`, `[[square]]` and `[[mesh]]` entries.
+//! Meshes are STL, OBJ, PLY or glTF files, and keep their own materials unless `material` is set.
+//! Objects with an emmitive material light the scene like area lights.
+//!
+//! Meshes placed many times, like the trees of a forest, are loaded once as `[meshes.<name>]` tables taking the `path`,
+//! `material` and `flat_shading` of `[[mesh]]`. Each `[[instance]]` entry then places them without copying their triangles:
+//!
+//! ```toml
+//! [meshes.tree]
+//! path = "tree.obj"
+//!
+//! [[instance]]
+//! mesh = "tree"
+//! translation = [4.0, 0.0, 2.0]
+//! rotation = [0.0, 45.0, 0.0]
+//! scale = 1.5
+//! ```
+//!
+//! Spheres, meshes and instances can move while the shutter is open, set with `shutter = [0.0, 1.0]` in `[camera]`.
+//! Their keyframes translate, rotate and scale them around their position, `interpolation` setting how each one goes to the next:
+//!
+//! ```toml
+//! [[sphere]]
+//! position = [0.0, 5.0, 0.0]
+//! radius = 5.0
+//! material = "ground"
+//! motion = [ { time = 0.0, interpolation = "bezier" }, { time = 1.0, translation = [4.0, 0.0, 0.0] } ]
+//! ```
+//!
+//! The camera is animated the same way, its keyframes taking a `position` and a `rotation` or `look_at`,
+//! which default to its static placement. Times are in seconds when rendering frame sequences.
+//!
+//! Besides the sun, lights are declared with `[[point_light]]`, `[[spot_light]]` and `[[area_light]]` entries.
+//! Their power is in watts, and colors default to white:
+//!
+//! ```toml
+//! [[spot_light]]
+//! position = [0.0, 10.0, 0.0]
+//! direction = [0.0, -1.0, 0.0]
+//! angle = 30.0 # Half angle of the cone, in degrees
+//! falloff = 0.2 # Fraction of the cone over which the light fades out
+//! power = 5000.0
+//!
+//! [[area_light]]
+//! kind = "rect" # Facing down unless rotated, or "disk" with a `normal` and `radius`, or "sphere" with a `radius`
+//! center = [0.0, 20.0, 0.0]
+//! size = [4.0, 4.0]
+//! rotation = [0.0, 0.0, 0.0]
+//! power = 2000.0
+//! color = [1.0, 0.9, 0.8]
+//! ```
+
+use std::{collections::HashMap, fmt, ops::Range, path::{Path, PathBuf}, sync::Arc};
+
+use glam::{Vec2, Vec3, Quat, EulerRot, Mat4};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+    camera::{Camera, Aperture, Projection, FieldOfView, FULL_FRAME_SENSOR_WIDTH},
+    material::{Material, Color},
+    light::Light,
+    sky::Sky,
+    environment::EnvironmentMap,
+    scene::Scene,
+    shape::*,
+    mesh::{load_mesh, Mesh},
+    gltf_file::load_gltf_file,
+    instance::Instance,
+    motion::{Motion, Moving, Keyframe, Transform, Interpolation},
+    texture::{Texture, TextureWrapping}
+};
+
+/// Error raised while loading a scene, pointing at the offending location in the file when possible
+#[derive(Debug)]
+pub struct SceneError {
+    pub path: PathBuf,
+    /// Line and column, starting at 1
+    pub location: Option<(usize, usize)>,
+    pub message: String
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "{}:{}:{}: {}", self.path.display(), line, column, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message)
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: Spanned<CameraDesc>,
+    /// Older way of placing the sun
+    light: Option<Spanned<LightDesc>>,
+    sky: Option<Spanned<SkyDesc>>,
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    point_light: Vec<PointLightDesc>,
+    #[serde(default)]
+    spot_light: Vec<SpotLightDesc>,
+    #[serde(default)]
+    area_light: Vec<AreaLightDesc>,
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    sphere: Vec<SphereDesc>,
+    #[serde(default)]
+    plane: Vec<PlaneDesc>,
+    #[serde(default)]
+    triangle: Vec<TriangleDesc>,
+    #[serde(default)]
+    square: Vec<SquareDesc>,
+    #[serde(default)]
+    mesh: Vec<MeshDesc>,
+    #[serde(default)]
+    meshes: HashMap<String, SharedMeshDesc>,
+    #[serde(default)]
+    instance: Vec<InstanceDesc>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    /// Optional when taken from a glTF file
+    position: Option<Vec3>,
+    rotation: Option<Vec3>,
+    /// glTF file to take the placement and field of view of a camera from
+    from: Option<Spanned<PathBuf>>,
+    /// Camera of the glTF file, its first one when absent
+    name: Option<String>,
+    /// Point to look at instead of a rotation
+    look_at: Option<Vec3>,
+    up: Option<Vec3>,
+    /// Vertical field of view in degrees
+    fov: Option<f32>,
+    horizontal_fov: Option<f32>,
+    /// Focal length in millimeters, alternative to the field of view
+    focal_length: Option<f32>,
+    #[serde(default = "default_sensor_width")]
+    sensor_width: f32,
+    #[serde(default)]
+    projection: ProjectionDesc,
+    /// Lens radius, zero for a pinhole
+    #[serde(default)]
+    aperture: f32,
+    focus_distance: Option<f32>,
+    /// Number of sides of a polygonal aperture, round when absent
+    aperture_blades: Option<u32>,
+    /// Rotation of the polygonal aperture in degrees
+    #[serde(default)]
+    aperture_rotation: f32,
+    /// Times at which the shutter opens and closes, for motion blur
+    shutter: Option<[f32; 2]>,
+    motion: Option<Spanned<Vec<Spanned<CameraKeyframeDesc>>>>
+}
+
+fn default_sensor_width() -> f32 { FULL_FRAME_SENSOR_WIDTH }
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+enum ProjectionDesc {
+    #[default]
+    Perspective,
+    Orthographic { height: f32 },
+    Equirectangular,
+    /// Field of view in degrees
+    Fisheye {
+        #[serde(default = "default_fisheye_fov")]
+        fov: f32
+    }
+}
+
+fn default_fisheye_fov() -> f32 { 180.0 }
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LightDesc {
+    direction: Vec3
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SkyDesc {
+    direction: Option<Vec3>,
+    /// Angles of the sun in degrees
+    elevation: Option<f32>,
+    #[serde(default)]
+    azimuth: f32,
+    turbidity: Option<f32>,
+    ground_albedo: Option<[f32; 3]>,
+    /// Angular diameter of the sun in degrees
+    sun_size: Option<f32>,
+    intensity: Option<f32>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    path: Spanned<PathBuf>,
+    /// Around the vertical axis, in degrees
+    #[serde(default)]
+    rotation: f32,
+    intensity: Option<f32>
+}
+
+fn default_light_color() -> [f32; 3] { [1.0; 3] }
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointLightDesc {
+    position: Vec3,
+    /// In watts
+    power: f32,
+    #[serde(default = "default_light_color")]
+    color: [f32; 3]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpotLightDesc {
+    position: Vec3,
+    direction: Vec3,
+    /// Half angle of the cone in degrees
+    angle: f32,
+    #[serde(default)]
+    falloff: f32,
+    power: f32,
+    #[serde(default = "default_light_color")]
+    color: [f32; 3]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+enum AreaLightDesc {
+    /// Facing down before the rotation, in degrees
+    Rect {
+        center: Vec3,
+        size: Vec2,
+        #[serde(default)]
+        rotation: Vec3,
+        power: f32,
+        #[serde(default = "default_light_color")]
+        color: [f32; 3]
+    },
+    Disk {
+        center: Vec3,
+        #[serde(default = "default_disk_normal")]
+        normal: Vec3,
+        radius: f32,
+        power: f32,
+        #[serde(default = "default_light_color")]
+        color: [f32; 3]
+    },
+    Sphere {
+        center: Vec3,
+        radius: f32,
+        power: f32,
+        #[serde(default = "default_light_color")]
+        color: [f32; 3]
+    }
+}
+
+fn default_disk_normal() -> Vec3 { Vec3::NEG_Y }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum WrappingDesc {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    path: Spanned<PathBuf>,
+    wrapping: Option<WrappingDesc>
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum MaterialKindDesc {
+    Lambertian,
+    Metal,
+    Transparent,
+    Emmitive
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    kind: MaterialKindDesc,
+    albedo: Option<[f32; 3]>,
+    roughness: Option<f32>,
+    refraction_index: Option<f32>,
+    color: Option<[f32; 3]>,
+    intensity: Option<f32>,
+    two_sided: Option<bool>,
+    texture: Option<Spanned<String>>,
+    normal_map: Option<Spanned<String>>,
+    texture_size: Option<Vec2>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    position: Vec3,
+    radius: f32,
+    material: Spanned<String>,
+    motion: Option<Spanned<Vec<KeyframeDesc>>>
+}
+
+/// Transform relative to the placement of the object, around its position
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f32,
+    #[serde(default)]
+    translation: Vec3,
+    #[serde(default)]
+    rotation: Vec3,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default)]
+    interpolation: InterpolationDesc
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum InterpolationDesc {
+    #[default]
+    Linear,
+    Bezier
+}
+
+impl From<InterpolationDesc> for Interpolation {
+    fn from(desc: InterpolationDesc) -> Self {
+        match desc {
+            InterpolationDesc::Linear => Interpolation::Linear,
+            InterpolationDesc::Bezier => Interpolation::Bezier
+        }
+    }
+}
+
+/// Placement of the camera, defaulting to its static one
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraKeyframeDesc {
+    time: f32,
+    position: Option<Vec3>,
+    rotation: Option<Vec3>,
+    look_at: Option<Vec3>,
+    up: Option<Vec3>,
+    #[serde(default)]
+    interpolation: InterpolationDesc
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDesc {
+    position: Vec3,
+    normal: Vec3,
+    material: Spanned<String>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleDesc {
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    tex: Option<[Vec2; 3]>,
+    material: Spanned<String>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SquareDesc {
+    center: Vec3,
+    size: Vec2,
+    #[serde(default)]
+    rotation: Vec3,
+    material: Spanned<String>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    path: Spanned<PathBuf>,
+    #[serde(default)]
+    translation: Vec3,
+    #[serde(default)]
+    rotation: Vec3,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    /// Overrides the materials of the mesh file
+    material: Option<Spanned<String>>,
+    /// Ignores vertex normals, giving a faceted look
+    #[serde(default)]
+    flat_shading: bool,
+    motion: Option<Spanned<Vec<KeyframeDesc>>>
+}
+
+/// Mesh loaded once and placed by instances
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SharedMeshDesc {
+    path: Spanned<PathBuf>,
+    material: Option<Spanned<String>>,
+    #[serde(default)]
+    flat_shading: bool
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceDesc {
+    mesh: Spanned<String>,
+    #[serde(default)]
+    translation: Vec3,
+    #[serde(default)]
+    rotation: Vec3,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    motion: Option<Spanned<Vec<KeyframeDesc>>>
+}
+
+fn default_scale() -> f32 { 1.0 }
+
+/// Places an object from its translation, rotation in degrees and uniform scale
+fn placement(translation: Vec3, rotation: Vec3, scale: f32) -> Mat4 {
+    Mat4::from_translation(translation) * Mat4::from_quat(rotation_from_degrees(rotation)) * Mat4::from_scale(Vec3::splat(scale))
+}
+
+/// Converts euler angles in degrees to a rotation, applied in Y, X, Z order
+fn rotation_from_degrees(rotation: Vec3) -> Quat {
+    Quat::from_euler(EulerRot::YXZ, rotation.y.to_radians(), rotation.x.to_radians(), rotation.z.to_radians())
+}
+
+/// Keeps track of the scene source to turn byte spans into errors
+struct Context<'p> {
+    path: &'p Path,
+    source: &'p str
+}
+
+impl Context<'_> {
+    fn error(&self, span: Option<Range<usize>>, message: impl Into<String>) -> SceneError {
+        let location = span.map(|span| {
+            let before = &self.source[..span.start.min(self.source.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+            (line, column)
+        });
+
+        SceneError { path: self.path.to_owned(), location, message: message.into() }
+    }
+
+    /// Resolves a path relative to the directory of the scene file
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.path.parent().unwrap_or(Path::new("")).join(path)
+    }
+
+    /// Orientation of a camera given either as a rotation or a point to look at
+    fn orientation(&self, span: Range<usize>, position: Vec3, rotation: Option<Vec3>, look_at: Option<Vec3>, up: Option<Vec3>) -> Result<Option<Quat>, SceneError> {
+        match ( rotation, look_at ) {
+            ( Some(_), Some(_) ) => Err(self.error(Some(span), "camera has both `rotation` and `look_at`")),
+            ( Some(rotation), None ) => Ok(Some(rotation_from_degrees(rotation))),
+            ( None, Some(target) ) => Ok(Some(Camera::look_at(position, target, up.unwrap_or(Vec3::Y)).orientation)),
+            ( None, None ) => Ok(None)
+        }
+    }
+
+    fn motion(&self, keyframes: &Spanned<Vec<KeyframeDesc>>, position: Vec3) -> Result<Motion, SceneError> {
+        if keyframes.get_ref().is_empty() {
+            return Err(self.error(Some(keyframes.span()), "motion needs at least one keyframe"));
+        }
+
+        Ok(Motion::new(keyframes.get_ref().iter().map(|k| Keyframe::new(k.time, Transform {
+            translation: position + k.translation,
+            rotation: rotation_from_degrees(k.rotation),
+            scale: Vec3::splat(k.scale)
+        }).set_interpolation(k.interpolation.into())).collect()))
+    }
+
+    /// Camera of a glTF file, in the space of the file
+    fn gltf_camera(&self, from: &Spanned<PathBuf>, name: Option<&str>) -> Result<Camera, SceneError> {
+        let file = self.resolve(from.get_ref());
+
+        let scene = load_gltf_file(&file)
+            .map_err(|e| self.error(Some(from.span()), format!("could not load camera: {}", e)))?;
+
+        scene.cameras.into_iter()
+            .find(|c| name.is_none_or(|name| c.name.as_deref() == Some(name)))
+            .map(|c| c.camera)
+            .ok_or_else(|| self.error(Some(from.span()), match name {
+                Some(name) => format!("no camera named `{}` in `{}`", name, file.display()),
+                None => format!("no camera in `{}`", file.display())
+            }))
+    }
+
+    fn lookup<T: Clone>(&self, names: &HashMap<String, T>, name: &Spanned<String>, what: &str) -> Result<T, SceneError> {
+        names.get(name.get_ref()).cloned()
+            .ok_or_else(|| self.error(Some(name.span()), format!("unknown {} `{}`", what, name.get_ref())))
+    }
+}
+
+/// Reads and loads a scene file, along with the textures and meshes it references
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| SceneError { path: path.to_owned(), location: None, message: e.to_string() })?;
+
+    parse_scene(path, &source)
+}
+
+/// Parses a scene from its source, `path` is used for error messages and to resolve relative paths
+pub fn parse_scene(path: &Path, source: &str) -> Result<Scene, SceneError> {
+    let cx = Context { path, source };
+
+    let desc: SceneDesc = toml::from_str(source)
+        .map_err(|e| cx.error(e.span(), e.message().trim_end()))?;
+
+    let mut textures = HashMap::new();
+    for (name, texture) in &desc.textures {
+        let file = cx.resolve(texture.path.get_ref());
+
+        let mut loaded = Texture::from_file(&file)
+            .map_err(|e| cx.error(Some(texture.path.span()), format!("could not load texture `{}`: {}", file.display(), e)))?;
+
+        if let Some(wrapping) = &texture.wrapping {
+            loaded = loaded.set_wrapping(match wrapping {
+                WrappingDesc::Repeat => TextureWrapping::Repeat,
+                WrappingDesc::MirroredRepeat => TextureWrapping::MirroredRepeat,
+                WrappingDesc::ClampToEdge => TextureWrapping::ClampToEdge
+            });
+        }
+
+        textures.insert(name.clone(), Arc::new(loaded));
+    }
+
+    let mut materials = HashMap::new();
+    for (name, spanned) in &desc.materials {
+        let m = spanned.get_ref();
+        let color = |c: Option<[f32; 3]>| c.map_or(Color::WHITE, |[r, g, b]| Color::new(r, g, b));
+
+        let mut material = match m.kind {
+            MaterialKindDesc::Lambertian => Material::new_lambertian(color(m.albedo)),
+            MaterialKindDesc::Metal => Material::new_rough_metal(color(m.albedo), m.roughness.unwrap_or(0.0)),
+            MaterialKindDesc::Transparent => {
+                let index = m.refraction_index
+                    .ok_or_else(|| cx.error(Some(spanned.span()), format!("transparent material `{}` needs a `refraction_index`", name)))?;
+
+                Material::new_transparent(index)
+            },
+            MaterialKindDesc::Emmitive => Material::new_emmitive(color(m.color), m.intensity.unwrap_or(1.0))
+                .set_two_sided(m.two_sided.unwrap_or(true))
+        };
+
+        if let Some(size) = m.texture_size {
+            material = material.set_size(( size.x, size.y ));
+        }
+        if let Some(texture) = &m.texture {
+            material = material.set_texture(cx.lookup(&textures, texture, "texture")?);
+        }
+        if let Some(normal_map) = &m.normal_map {
+            material = material.set_normal(cx.lookup(&textures, normal_map, "texture")?);
+        }
+
+        materials.insert(name.clone(), material);
+    }
+
+    let material = |name: &Spanned<String>| cx.lookup(&materials, name, "material");
+
+    let camera_span = desc.camera.span();
+    let camera_desc = desc.camera.get_ref();
+
+    let imported = match ( &camera_desc.from, &camera_desc.name ) {
+        ( Some(from), name ) => Some(cx.gltf_camera(from, name.as_deref())?),
+        ( None, Some(_) ) => return Err(cx.error(Some(camera_span), "camera has a `name` but no glTF file to take it `from`")),
+        ( None, None ) => None
+    };
+
+    let position = match ( camera_desc.position, &imported ) {
+        ( Some(position), _ ) => position,
+        ( None, Some(camera) ) => camera.position,
+        ( None, None ) => return Err(cx.error(Some(camera_span), "camera needs a `position`"))
+    };
+
+    let orientation = cx.orientation(camera_span.clone(), position, camera_desc.rotation, camera_desc.look_at, camera_desc.up)?
+        .or(imported.as_ref().map(|camera| camera.orientation))
+        .unwrap_or(Quat::IDENTITY);
+
+    let fov = match ( camera_desc.fov, camera_desc.horizontal_fov, camera_desc.focal_length ) {
+        ( None, None, None ) => imported.as_ref().map(|camera| camera.fov),
+        ( Some(fov), None, None ) => Some(FieldOfView::Vertical(fov.to_radians())),
+        ( None, Some(fov), None ) => Some(FieldOfView::Horizontal(fov.to_radians())),
+        ( None, None, Some(focal_length) ) => Some(FieldOfView::from_focal_length(focal_length, camera_desc.sensor_width)),
+        _ => return Err(cx.error(Some(camera_span), "camera can only have one of `fov`, `horizontal_fov` and `focal_length`"))
+    };
+
+    let projection = match camera_desc.projection {
+        ProjectionDesc::Perspective => Projection::Perspective,
+        ProjectionDesc::Orthographic { height } => Projection::Orthographic { height },
+        ProjectionDesc::Equirectangular => Projection::Equirectangular,
+        ProjectionDesc::Fisheye { fov } => Projection::Fisheye { fov: fov.to_radians() }
+    };
+
+    let mut camera = Camera::new(position, orientation)
+        .set_projection(projection)
+        .set_aperture(camera_desc.aperture);
+    if let Some(fov) = fov {
+        camera = camera.set_fov(fov);
+    }
+    if let Some(keyframes) = &camera_desc.motion {
+        if keyframes.get_ref().is_empty() {
+            return Err(cx.error(Some(keyframes.span()), "motion needs at least one keyframe"));
+        }
+
+        let keyframes = keyframes.get_ref().iter().map(|k| {
+            let ( span, k ) = ( k.span(), k.get_ref() );
+            let position = k.position.unwrap_or(position);
+            let rotation = cx.orientation(span, position, k.rotation, k.look_at, k.up)?.unwrap_or(orientation);
+
+            Ok(Keyframe::new(k.time, Transform { translation: position, rotation, scale: Vec3::ONE }).set_interpolation(k.interpolation.into()))
+        }).collect::<Result<_, SceneError>>()?;
+
+        camera = camera.set_animation(Motion::new(keyframes));
+    }
+    if let Some([open, close]) = camera_desc.shutter {
+        camera = camera.set_shutter(open, close);
+    }
+    if let Some(distance) = camera_desc.focus_distance {
+        camera = camera.set_focus_distance(distance);
+    }
+    if let Some(blades) = camera_desc.aperture_blades {
+        camera = camera.set_aperture_shape(Aperture::Polygon { blades, rotation: camera_desc.aperture_rotation.to_radians() });
+    }
+
+    let mut scene = Scene::new(camera);
+
+    match ( &desc.light, &desc.sky ) {
+        ( Some(_), Some(sky) ) => return Err(cx.error(Some(sky.span()), "the sun is placed by both `[light]` and `[sky]`")),
+        ( Some(light), None ) => scene = scene.set_light_direction(light.get_ref().direction),
+        ( None, Some(spanned) ) => {
+            let s = spanned.get_ref();
+
+            let mut sky = match ( s.direction, s.elevation ) {
+                ( Some(_), Some(_) ) => return Err(cx.error(Some(spanned.span()), "sky has both `direction` and `elevation`")),
+                ( Some(direction), None ) => Sky::new(direction),
+                ( None, Some(elevation) ) => Sky::from_angles(elevation.to_radians(), s.azimuth.to_radians()),
+                ( None, None ) => scene.sky.clone()
+            };
+
+            if let Some(turbidity) = s.turbidity {
+                sky = sky.set_turbidity(turbidity);
+            }
+            if let Some([r, g, b]) = s.ground_albedo {
+                sky = sky.set_ground_albedo(Color::new(r, g, b));
+            }
+            if let Some(size) = s.sun_size {
+                sky = sky.set_sun_angular_diameter(size.to_radians());
+            }
+            if let Some(intensity) = s.intensity {
+                sky = sky.set_intensity(intensity);
+            }
+
+            scene = scene.set_sky(sky);
+        },
+        ( None, None ) => {}
+    }
+
+    if let Some(environment) = &desc.environment {
+        let file = cx.resolve(environment.path.get_ref());
+
+        let map = EnvironmentMap::from_file(&file)
+            .map_err(|e| cx.error(Some(environment.path.span()), format!("could not load environment `{}`: {}", file.display(), e)))?;
+
+        scene = scene.set_environment(map
+            .set_rotation(environment.rotation.to_radians())
+            .set_intensity(environment.intensity.unwrap_or(1.0)));
+    }
+
+    let light_color = |[r, g, b]: [f32; 3]| Color::new(r, g, b);
+
+    for l in &desc.point_light {
+        scene.lights.push(Light::new_point(l.position, light_color(l.color), l.power));
+    }
+
+    for l in &desc.spot_light {
+        scene.lights.push(Light::new_spot(l.position, l.direction, l.angle.to_radians(), l.falloff, light_color(l.color), l.power));
+    }
+
+    for l in &desc.area_light {
+        scene.lights.push(match *l {
+            AreaLightDesc::Rect { center, size, rotation, power, color } => {
+                let rotation = rotation_from_degrees(rotation);
+                Light::new_rect(center, rotation * Vec3::new(size.x, 0.0, 0.0), rotation * Vec3::new(0.0, 0.0, size.y), light_color(color), power)
+            },
+            AreaLightDesc::Disk { center, normal, radius, power, color } => Light::new_disk(center, normal, radius, light_color(color), power),
+            AreaLightDesc::Sphere { center, radius, power, color } => Light::new_sphere(center, radius, light_color(color), power)
+        });
+    }
+
+    for s in &desc.sphere {
+        if let Some(motion) = &s.motion {
+            let motion = cx.motion(motion, s.position)?;
+            scene.push(Moving::new(Sphere { pos: Vec3::ZERO, radius: s.radius, material: material(&s.material)? }, motion));
+        }
+        else {
+            scene.push(Sphere { pos: s.position, radius: s.radius, material: material(&s.material)? });
+        }
+    }
+
+    for p in &desc.plane {
+        scene.push(Plane { pos: p.position, normal: p.normal.normalize(), material: material(&p.material)? });
+    }
+
+    for t in &desc.triangle {
+        let [v0, v1, v2] = t.vertices;
+        let flat = (v1 - v0).cross(v2 - v0).normalize();
+        let normals = t.normals.map_or([flat; 3], |n| n.map(Vec3::normalize));
+        let tex = t.tex.unwrap_or([Vec2::ZERO; 3]);
+
+        let vertex = |i: usize| Vertex { pos: t.vertices[i], normal: normals[i], tex: tex[i] };
+
+        scene.push(Triangle::new(vertex(0), vertex(1), vertex(2), material(&t.material)?));
+    }
+
+    for s in &desc.square {
+        let ( t1, t2 ) = square(s.center, s.size, rotation_from_degrees(s.rotation), material(&s.material)?);
+
+        scene.push(t1);
+        scene.push(t2);
+    }
+
+    for m in &desc.mesh {
+        let material = m.material.as_ref().map(material).transpose()?;
+        let file = cx.resolve(m.path.get_ref());
+
+        // Moving meshes are placed by their motion, around their translation
+        let motion = m.motion.as_ref().map(|motion| cx.motion(motion, m.translation)).transpose()?;
+        let translation = if motion.is_some() { Vec3::ZERO } else { m.translation };
+
+        let transform = placement(translation, m.rotation, m.scale);
+
+        let triangles = load_mesh(&file)
+            .map_err(|e| cx.error(Some(m.path.span()), format!("could not load mesh: {}", e)))?;
+
+        let triangles = triangles.into_iter().map(|mut t| {
+            if let Some(material) = &material {
+                t.material = material.clone();
+            }
+            t.set_flat_shading(m.flat_shading)
+        });
+
+        match motion {
+            // Moved as a whole, rather than triangle by triangle
+            Some(motion) => scene.push(Moving::new(Instance::new(Arc::new(Mesh::new(triangles.collect())), transform), motion)),
+            None => scene.extend(triangles.map(|t| t.transform(transform)))
+        }
+    }
+
+    let mut meshes = HashMap::new();
+    for (name, m) in &desc.meshes {
+        let material = m.material.as_ref().map(material).transpose()?;
+
+        let triangles = load_mesh(cx.resolve(m.path.get_ref()))
+            .map_err(|e| cx.error(Some(m.path.span()), format!("could not load mesh: {}", e)))?;
+
+        let triangles = triangles.into_iter().map(|mut t| {
+            if let Some(material) = &material {
+                t.material = material.clone();
+            }
+            t.set_flat_shading(m.flat_shading)
+        }).collect();
+
+        meshes.insert(name.clone(), Arc::new(Mesh::new(triangles)));
+    }
+
+    for i in &desc.instance {
+        let mesh = cx.lookup(&meshes, &i.mesh, "mesh")?;
+
+        let motion = i.motion.as_ref().map(|motion| cx.motion(motion, i.translation)).transpose()?;
+        let translation = if motion.is_some() { Vec3::ZERO } else { i.translation };
+
+        let instance = Instance::new(mesh, placement(translation, i.rotation, i.scale));
+        match motion {
+            Some(motion) => scene.push(Moving::new(instance, motion)),
+            None => scene.push(instance)
+        }
+    }
+
+    Ok(scene)
+}
@@ -1,6 +1,6 @@
-use glam::{Vec3, Vec2, Mat4};
+use glam::{Vec3, Vec2, Mat4, Quat, BVec3};
 
-use crate::material::Material;
+use crate::material::{Material, Color};
 
 pub trait Shape: std::fmt::Debug
 {
@@ -15,6 +15,9 @@ pub struct Rect {
 }
 
 impl Rect {
+    /// Bounds everything, for shapes that extend without limit
+    ///
+    /// Any ray intersects it, but it has an infinite surface area, and the BVH keeps such shapes out of its hierarchy.
     pub fn infinite() -> Self {
         Rect {
             min: Vec3::splat(f32::NEG_INFINITY),
@@ -22,8 +25,52 @@ impl Rect {
         }
     }
 
+    /// Bounds nothing, the starting point of unions
+    pub fn empty() -> Self {
+        Rect {
+            min: Vec3::splat(f32::INFINITY),
+            max: Vec3::splat(f32::NEG_INFINITY)
+        }
+    }
+
+    pub fn union(self, other: Rect) -> Self {
+        Rect {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max)
+        }
+    }
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Rect {
+            min: self.min.min(point),
+            max: self.max.max(point)
+        }
+    }
+
+    /// Bounds the box once transformed, through its eight corners
+    pub fn transform(self, mat: Mat4) -> Self {
+        (0..8).fold(Rect::empty(), |bound, i| {
+            let corner = Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), self.max, self.min);
+
+            bound.grow(mat.transform_point3(corner))
+        })
+    }
+
+    /// Whether the box has finite bounds on every axis, which an empty one doesn't
+    pub fn is_finite(&self) -> bool {
+        self.min.is_finite() && self.max.is_finite()
+    }
+
+    /// Infinite for unbounded boxes, even flat ones
+    pub fn surface_area(&self) -> f32 {
+        if !self.is_finite() { return f32::INFINITY }
+
+        let size = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (size.x*size.y + size.y*size.z + size.z*size.x)
+    }
+
     pub fn order_components(mut self) -> Self {
-        let this = self.clone();
+        let this = self;
 
         self.min = this.min.min(this.max);
         self.max = this.max.max(this.min);
@@ -43,13 +90,13 @@ impl Shape for Rect {
 }
 
 #[derive(Debug)]
-pub struct Sphere<'a> {
+pub struct Sphere {
     pub pos: Vec3,
     pub radius: f32,
-    pub material: Material<'a>
+    pub material: Material
 }
 
-impl Shape for Sphere<'_> {
+impl Shape for Sphere {
     fn position(&self) -> Vec3 {
         self.pos
     }
@@ -63,13 +110,13 @@ impl Shape for Sphere<'_> {
 }
 
 #[derive(Debug)]
-pub struct Plane<'a> {
+pub struct Plane {
     pub pos: Vec3,
     pub normal: Vec3,
-    pub material: Material<'a>
+    pub material: Material
 }
 
-impl Shape for Plane<'_> {
+impl Shape for Plane {
     fn position(&self) -> Vec3 {
         self.pos
     }
@@ -95,18 +142,22 @@ pub struct Vertex {
 }
 
 #[derive(Debug)]
-pub struct Triangle<'a> {
+pub struct Triangle {
     pub p0: Vertex,
     pub p1: Vertex,
     pub p2: Vertex,
-    pub material: Material<'a>,
+    pub material: Material,
+    /// Per-vertex colors multiplying the albedo of the material
+    pub colors: Option<[Color; 3]>,
+    /// Ignores vertex normals and shades with the normal of the face
+    pub flat: bool,
 
     pub edge1: Vec3,
     pub edge2: Vec3,
     pub edge3: Vec3
 }
 
-impl Shape for Triangle<'_> {
+impl Shape for Triangle {
     fn position(&self) -> Vec3 {
         (self.p0.pos + self.p1.pos + self.p2.pos) / 3.0
     }
@@ -119,17 +170,41 @@ impl Shape for Triangle<'_> {
     }
 }
 
-impl<'a> Triangle<'a> {
-    pub fn new(p0: Vertex, p1: Vertex, p2: Vertex, material: Material<'a>) -> Self {
-        Triangle { p0, p1, p2, material, edge1: Vec3::ZERO, edge2: Vec3::ZERO, edge3: Vec3::ZERO}
+impl Triangle {
+    pub fn new(p0: Vertex, p1: Vertex, p2: Vertex, material: Material) -> Self {
+        Triangle { p0, p1, p2, material, colors: None, flat: false, edge1: Vec3::ZERO, edge2: Vec3::ZERO, edge3: Vec3::ZERO}
             .precompute()
     }
 
+    pub fn set_vertex_colors(mut self, colors: [Color; 3]) -> Self {
+        self.colors = Some(colors);
+        self
+    }
+
+    pub fn set_flat_shading(mut self, flat: bool) -> Self {
+        self.flat = flat;
+        self
+    }
+
     pub fn transform(mut self, mat: Mat4) -> Self {
         self.p0.pos = mat.transform_point3(self.p0.pos);
         self.p1.pos = mat.transform_point3(self.p1.pos);
         self.p2.pos = mat.transform_point3(self.p2.pos);
 
+        // Normals are transformed by the inverse transpose to stay perpendicular under non-uniform scaling
+        let normal_mat = mat.inverse().transpose();
+        self.p0.normal = normal_mat.transform_vector3(self.p0.normal).normalize_or_zero();
+        self.p1.normal = normal_mat.transform_vector3(self.p1.normal).normalize_or_zero();
+        self.p2.normal = normal_mat.transform_vector3(self.p2.normal).normalize_or_zero();
+
+        // Mirroring reverses the winding, which would turn the front of the triangle inwards
+        if mat.determinant() < 0.0 {
+            std::mem::swap(&mut self.p0, &mut self.p2);
+            if let Some(colors) = &mut self.colors {
+                colors.swap(0, 2);
+            }
+        }
+
         self.precompute()
     }
 
@@ -141,27 +216,54 @@ impl<'a> Triangle<'a> {
         self
     }
 
+    /// Weights of each vertex for a point on the plane of the triangle
+    ///
+    /// Solved in 3D by projecting onto the two edges, so it works whatever the orientation of the triangle.
     pub fn barycentric_weigths(&self, p: Vec3) -> (f32, f32, f32) {
-        // v1 - v2 -> -edge1
-        // v1 - v3 -> -edge2
-        // v2 - v3 -> -edge3
-        // v3 - v2 -> edge3
-        // v3 - v1 -> edge2
+        let to_p = p - self.p0.pos;
+
+        let d00 = self.edge1.dot(self.edge1);
+        let d01 = self.edge1.dot(self.edge2);
+        let d11 = self.edge2.dot(self.edge2);
+        let d20 = to_p.dot(self.edge1);
+        let d21 = to_p.dot(self.edge2);
 
-        let div = -self.edge3.y * (-self.edge2.x) + self.edge3.x * (-self.edge2.y);
+        let div = d00 * d11 - d01 * d01;
 
-        let w0 = ( -self.edge3.y * (p.x - self.p2.pos.x) + self.edge3.x * (p.y - self.p2.pos.y) ) / div;
-        let w1 = ( self.edge2.y * (p.x - self.p2.pos.x) + -self.edge2.x * (p.y - self.p2.pos.y)) / div;
-        let w2 = 1.0 - w0 - w1;
+        let w1 = (d11 * d20 - d01 * d21) / div;
+        let w2 = (d00 * d21 - d01 * d20) / div;
+        let w0 = 1.0 - w1 - w2;
 
         (w0, w1, w2)
     }
 }
 
+/// Creates a z-axis aligned rectangle out of two triangles
+pub fn square( center: Vec3, size: Vec2, orientation: Quat, material: Material ) -> ( Triangle, Triangle ) {
+    let p1 = orientation * Vec3::new(-size.x/2.0, 0.0, -size.y/2.0);
+    let p2 = orientation * Vec3::new( size.x/2.0, 0.0, -size.y/2.0);
+    let p3 = orientation * Vec3::new(-size.x/2.0, 0.0,  size.y/2.0);
+    let p4 = orientation * Vec3::new( size.x/2.0, 0.0,  size.y/2.0);
+    let normal = orientation * Vec3::Y;
+
+    let p1 = Vertex { pos: center + p1, normal, tex: Vec2::new(0.0, 1.0) };
+    let p2 = Vertex { pos: center + p2, normal, tex: Vec2::new(1.0, 1.0) };
+    let p3 = Vertex { pos: center + p3, normal, tex: Vec2::new(0.0, 0.0) };
+    let p4 = Vertex { pos: center + p4, normal, tex: Vec2::new(1.0, 0.0) };
+
+    // Both wound so that their front faces `normal`
+    (
+        Triangle::new( p1, p3, p2, material.clone() ),
+        Triangle::new( p2, p3, p4, material )
+    )
+}
+
 #[derive(Debug)]
 pub struct Ray {
     pub start: Vec3,
-    pub dir: Vec3
+    pub dir: Vec3,
+    /// Instant within the shutter interval at which the ray travels
+    pub time: f32
 }
 
 impl Ray {
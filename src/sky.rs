@@ -0,0 +1,202 @@
+//! Daylight sky, after "A Practical Analytic Model for Daylight" by Preetham, Shirley and Smits
+
+use std::f32::consts::{PI, FRAC_PI_2};
+
+use glam::Vec3;
+
+use crate::{light::Sun, material::Color};
+
+/// Radiance of the scenes for a luminance of 1 kcd/m², putting a clear day in the range the tonemapping expects
+const SKY_SCALE: f32 = 0.05;
+
+/// Luminance of the sun above the atmosphere, in kcd/m²
+const SUN_LUMINANCE: f32 = 1.88e6;
+
+/// Wavelengths in micrometers standing for the red, green and blue channels
+const WAVELENGTHS: [f32; 3] = [ 0.680, 0.550, 0.440 ];
+
+/// Coefficients of the Perez distribution, describing how the sky varies away from the zenith and around the sun
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Perez([f32; 5]);
+
+impl Perez {
+    /// `theta` is the angle from the zenith, `gamma` the angle from the sun
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma*cos_gamma)
+    }
+}
+
+/// Sun and sky of a clear day
+///
+/// Directions below the horizon see a diffuse ground of color `ground_albedo`, lit by the sun and the sky.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    sun_direction: Vec3,
+    turbidity: f32,
+    ground_albedo: Color,
+    sun_angular_diameter: f32,
+    intensity: f32,
+    /// Luminance and chromaticity at the zenith, and their distributions
+    zenith: [f32; 3],
+    perez: [Perez; 3],
+    ground: Color
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3) -> Self {
+        Sky {
+            sun_direction: sun_direction.normalize(),
+            turbidity: 3.0,
+            ground_albedo: Color::splat(0.3),
+            sun_angular_diameter: 0.53f32.to_radians(),
+            intensity: 1.0,
+            zenith: [0.0; 3],
+            perez: [Perez([0.0; 5]); 3],
+            ground: Color::BLACK
+        }.update()
+    }
+
+    /// Places the sun from its height above the horizon and its angle clockwise from +Z when looking down, both in radians
+    pub fn from_angles(elevation: f32, azimuth: f32) -> Self {
+        Sky::new(Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), elevation.cos() * azimuth.cos()))
+    }
+
+    /// Normalized direction pointing towards the sun
+    pub fn sun_direction(&self) -> Vec3 { self.sun_direction }
+    pub fn turbidity(&self) -> f32 { self.turbidity }
+    pub fn ground_albedo(&self) -> Color { self.ground_albedo }
+    pub fn sun_angular_diameter(&self) -> f32 { self.sun_angular_diameter }
+    pub fn intensity(&self) -> f32 { self.intensity }
+
+    pub fn set_sun_direction(mut self, direction: Vec3) -> Self {
+        self.sun_direction = direction.normalize();
+        self.update()
+    }
+    /// Haziness of the atmosphere, from 2 for a very clear sky to 10 for a hazy one
+    pub fn set_turbidity(mut self, turbidity: f32) -> Self {
+        self.turbidity = turbidity;
+        self.update()
+    }
+    pub fn set_ground_albedo(mut self, albedo: Color) -> Self {
+        self.ground_albedo = albedo;
+        self.update()
+    }
+    /// Angle covered by the sun in radians, 0.53° for the real one. The sun keeps its radiance, so a larger one lights more
+    pub fn set_sun_angular_diameter(mut self, diameter: f32) -> Self {
+        self.sun_angular_diameter = diameter;
+        self.update()
+    }
+    /// Multiplies the radiance of the sun and sky
+    pub fn set_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self.update()
+    }
+
+    /// Angle between the sun and the zenith, the sky being that of a sun on the horizon once it has set
+    fn sun_theta(&self) -> f32 {
+        self.sun_direction.y.clamp(-1.0, 1.0).acos().min(FRAC_PI_2)
+    }
+
+    /// Recomputes the model after a change of parameters
+    fn update(mut self) -> Self {
+        let t = self.turbidity;
+        let theta = self.sun_theta();
+        let ( theta2, theta3 ) = ( theta*theta, theta*theta*theta );
+
+        let chi = (4.0/9.0 - t/120.0) * (PI - 2.0*theta);
+        let luminance = ((4.0453*t - 4.9710) * chi.tan() - 0.2155*t + 2.4192).max(0.0);
+
+        let x = t*t * (0.00166*theta3 - 0.00375*theta2 + 0.00209*theta)
+            + t * (-0.02903*theta3 + 0.06377*theta2 - 0.03202*theta + 0.00394)
+            + (0.11693*theta3 - 0.21196*theta2 + 0.06052*theta + 0.25886);
+        let y = t*t * (0.00275*theta3 - 0.00610*theta2 + 0.00317*theta)
+            + t * (-0.04214*theta3 + 0.08970*theta2 - 0.04153*theta + 0.00516)
+            + (0.15346*theta3 - 0.26756*theta2 + 0.06670*theta + 0.26688);
+
+        self.zenith = [ luminance, x, y ];
+        self.perez = [
+            Perez([ 0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251, 0.1206*t - 2.5771, -0.0670*t + 0.3703 ]),
+            Perez([ -0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452 ]),
+            Perez([ -0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529 ])
+        ];
+
+        // Irradiance on the ground, integrating the sky over the upper hemisphere
+        const STEPS: usize = 32;
+        let mut irradiance = Color::BLACK;
+        for i in 0..STEPS {
+            let cos_theta = (i as f32 + 0.5) / STEPS as f32;
+            let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+
+            for j in 0..2*STEPS {
+                let phi = (j as f32 + 0.5) / (2*STEPS) as f32 * 2.0*PI;
+                let dir = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+
+                // Uniform in cos θ, so each cell covers the same solid angle
+                irradiance += self.sky_radiance(dir) * (cos_theta * 2.0*PI / (2*STEPS*STEPS) as f32);
+            }
+        }
+
+        let sun = self.sun();
+        let sun_solid_angle = 2.0*PI * (1.0 - sun.cos_radius);
+        irradiance += sun.radiance * (sun_solid_angle * sun.direction.y.max(0.0));
+
+        self.ground = self.ground_albedo * irradiance / PI;
+        self
+    }
+
+    /// Radiance of the sky in the upper hemisphere, without the sun
+    fn sky_radiance(&self, dir: Vec3) -> Color {
+        let cos_theta = dir.y.max(0.01);
+        let gamma = dir.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let [ luminance, x, y ] = [0, 1, 2].map(|i| {
+            let perez = &self.perez[i];
+            self.zenith[i] * perez.eval(cos_theta, gamma) / perez.eval(1.0, self.sun_theta())
+        });
+
+        xyy_to_rgb(luminance, x, y) * (SKY_SCALE * self.intensity)
+    }
+
+    /// Radiance coming from a direction, the sun being left to [`Sky::sun`]
+    pub fn radiance(&self, dir: Vec3) -> Color {
+        if dir.y >= 0.0 { self.sky_radiance(dir) } else { self.ground }
+    }
+
+    /// The sun disc, dimmed and reddened by the atmosphere, black once it has set
+    pub fn sun(&self) -> Sun {
+        let elevation = self.sun_direction.y;
+        if elevation <= 0.0 {
+            return Sun::new(self.sun_direction, self.sun_angular_diameter, Color::BLACK);
+        }
+
+        // Relative path length through the atmosphere, which keeps finite at the horizon
+        let theta = elevation.acos();
+        let mass = 1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+
+        // Transmittance through air molecules and aerosols
+        let beta = 0.04608*self.turbidity - 0.04586;
+        let [r, g, b] = WAVELENGTHS.map(|lambda| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+
+            rayleigh * aerosol
+        });
+
+        Sun::new(self.sun_direction, self.sun_angular_diameter, Color::new(r, g, b) * (SUN_LUMINANCE * SKY_SCALE * self.intensity))
+    }
+}
+
+/// Converts a luminance and its chromaticity to linear sRGB
+fn xyy_to_rgb(luminance: f32, x: f32, y: f32) -> Color {
+    let big_x = x * luminance / y;
+    let big_z = (1.0 - x - y) * luminance / y;
+
+    Color::new(
+        (3.2406*big_x - 1.5372*luminance - 0.4986*big_z).max(0.0),
+        (-0.9689*big_x + 1.8758*luminance + 0.0415*big_z).max(0.0),
+        (0.0557*big_x - 0.2040*luminance + 1.0570*big_z).max(0.0)
+    )
+}
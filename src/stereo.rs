@@ -0,0 +1,79 @@
+//! Stereo pairs for VR and 3D displays
+
+use std::fmt;
+
+use image::{RgbImage, GenericImage};
+
+use crate::camera::{Camera, Eye};
+
+/// Two eyes on each side of a camera
+///
+/// Perspective cameras give parallel views with an off-axis shift, equirectangular ones give omnidirectional stereo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoRig {
+    /// Distance between the eyes, in scene units
+    pub interocular: f32,
+    /// Distance of the plane appearing at the depth of the screen, infinite for parallel views
+    pub convergence: f32
+}
+
+impl StereoRig {
+    pub fn new(interocular: f32) -> Self {
+        StereoRig { interocular, convergence: f32::INFINITY }
+    }
+
+    /// Fails if `convergence` is not positive
+    pub fn set_convergence(mut self, convergence: f32) -> Result<Self, InvalidConvergence> {
+        if convergence > 0.0 {
+            self.convergence = convergence;
+            Ok(self)
+        }
+        else {
+            Err(InvalidConvergence(convergence))
+        }
+    }
+
+    /// Left and right eye cameras
+    pub fn eyes(&self, camera: &Camera) -> [Camera; 2] {
+        [ -0.5, 0.5 ].map(|side| camera.clone().set_eye(Some(Eye {
+            offset: side * self.interocular,
+            convergence: self.convergence
+        })))
+    }
+}
+
+/// Convergence distance that is not positive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidConvergence(pub f32);
+
+impl fmt::Display for InvalidConvergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the convergence distance must be positive, not {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidConvergence {}
+
+/// How both eyes are packed in a single image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Left eye on the left
+    SideBySide,
+    /// Left eye on the top
+    TopBottom
+}
+
+/// Packs the images of both eyes, which must have the same size, into a single one
+pub fn combine(left: &RgbImage, right: &RgbImage, layout: StereoLayout) -> RgbImage {
+    let ( width, height ) = left.dimensions();
+
+    let ( mut image, x, y ) = match layout {
+        StereoLayout::SideBySide => ( RgbImage::new(width * 2, height), width, 0 ),
+        StereoLayout::TopBottom => ( RgbImage::new(width, height * 2), 0, height )
+    };
+
+    image.copy_from(left, 0, 0).expect("left eye larger than the combined image");
+    image.copy_from(right, x, y).expect("eyes of different sizes");
+
+    image
+}
@@ -0,0 +1,131 @@
+//! Binary and ASCII STL loading
+
+use std::path::Path;
+
+use glam::{Vec2, Vec3};
+
+use crate::{shape::{Triangle, Vertex}, material::{Material, Color}, mesh::MeshError};
+
+/// Size of the binary header, followed by the triangle count
+const HEADER_SIZE: usize = 80;
+/// Normal, three vertices and the attribute byte count
+const TRIANGLE_SIZE: usize = 4*3*4 + 2;
+
+/// Builds a triangle, recomputing its normal from the winding order when the stored one is zero
+fn make_triangle(normal: Vec3, v: [Vec3; 3]) -> Triangle {
+    let normal = if normal.length_squared() > 0.0 {
+        normal.normalize()
+    }
+    else {
+        (v[1] - v[0]).cross(v[2] - v[0]).normalize_or_zero()
+    };
+
+    let vertex = |pos: Vec3| Vertex { pos, normal, tex: Vec2::ZERO };
+
+    Triangle::new(vertex(v[0]), vertex(v[1]), vertex(v[2]), Material::new_lambertian(Color::WHITE))
+}
+
+/// Loads a binary or ASCII STL file
+///
+/// Binary files may also start with `solid`, so the file length is checked against the triangle count first.
+pub fn load_stl_file<P: AsRef<Path>>(file: P) -> Result<Vec<Triangle>, MeshError> {
+    let path = file.as_ref();
+    let data = std::fs::read(path).map_err(|e| MeshError::io(path, e))?;
+
+    let binary_count = data.get(HEADER_SIZE..HEADER_SIZE + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+
+    match binary_count {
+        Some(count) if HEADER_SIZE + 4 + count*TRIANGLE_SIZE == data.len() => Ok(parse_binary(&data, count)),
+        _ if data.starts_with(b"solid") => parse_ascii(path, &data),
+        Some(count) => Err(MeshError::Invalid {
+            path: path.to_owned(),
+            message: format!(
+                "header announces {} triangles ({} bytes) but the file is {} bytes long",
+                count, HEADER_SIZE + 4 + count*TRIANGLE_SIZE, data.len()
+            )
+        }),
+        None => Err(MeshError::Invalid { path: path.to_owned(), message: "file too short to be an STL file".to_owned() })
+    }
+}
+
+fn parse_binary(data: &[u8], count: usize) -> Vec<Triangle> {
+    let read_vec = |bytes: &[u8]| {
+        let f = |i: usize| f32::from_le_bytes(bytes[i*4..i*4 + 4].try_into().unwrap());
+        Vec3::new(f(0), f(1), f(2))
+    };
+
+    data[HEADER_SIZE + 4..].chunks_exact(TRIANGLE_SIZE)
+        .take(count)
+        .map(|t| make_triangle(
+            read_vec(&t[0..12]),
+            [ read_vec(&t[12..24]), read_vec(&t[24..36]), read_vec(&t[36..48]) ]
+        ))
+        .collect()
+}
+
+fn parse_ascii(path: &Path, data: &[u8]) -> Result<Vec<Triangle>, MeshError> {
+    let source = std::str::from_utf8(data)
+        .map_err(|_| MeshError::Invalid { path: path.to_owned(), message: "ASCII STL file is not valid UTF-8".to_owned() })?;
+
+    let mut triangles = Vec::new();
+
+    let mut normal = None;
+    let mut vertices = Vec::with_capacity(3);
+
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let mut tokens = line.split_whitespace();
+
+        let read_vec = |tokens: &mut dyn Iterator<Item = &str>| -> Result<Vec3, MeshError> {
+            let mut v = [0.0; 3];
+            for c in &mut v {
+                let token = tokens.next().ok_or_else(|| MeshError::parse(path, number, "expected three numbers"))?;
+                *c = token.parse().map_err(|_| MeshError::parse(path, number, format!("invalid number `{}`", token)))?;
+            }
+            Ok(Vec3::from_array(v))
+        };
+
+        match tokens.next() {
+            Some("facet") => {
+                if normal.is_some() {
+                    return Err(MeshError::parse(path, number, "`facet` inside another facet"));
+                }
+                if tokens.next() != Some("normal") {
+                    return Err(MeshError::parse(path, number, "expected `facet normal`"));
+                }
+
+                normal = Some(read_vec(&mut tokens)?);
+            },
+            Some("vertex") => {
+                if normal.is_none() {
+                    return Err(MeshError::parse(path, number, "`vertex` outside of a facet"));
+                }
+                if vertices.len() == 3 {
+                    return Err(MeshError::parse(path, number, "facet with more than three vertices"));
+                }
+
+                vertices.push(read_vec(&mut tokens)?);
+            },
+            Some("endfacet") => {
+                let Some(n) = normal.take() else {
+                    return Err(MeshError::parse(path, number, "`endfacet` without `facet`"));
+                };
+                if vertices.len() != 3 {
+                    return Err(MeshError::parse(path, number, format!("facet with {} vertices", vertices.len())));
+                }
+
+                triangles.push(make_triangle(n, [ vertices[0], vertices[1], vertices[2] ]));
+                vertices.clear();
+            },
+            Some("solid" | "endsolid" | "outer" | "endloop") | None => {},
+            Some(keyword) => return Err(MeshError::parse(path, number, format!("unexpected `{}`", keyword)))
+        }
+    }
+
+    if normal.is_some() {
+        return Err(MeshError::Invalid { path: path.to_owned(), message: "unterminated facet at the end of the file".to_owned() });
+    }
+
+    Ok(triangles)
+}
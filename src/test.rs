@@ -2,15 +2,1133 @@
 
 use glam::Vec3;
 
-use crate::{shape::{Sphere, Ray}, intersection::{Intersection, Traceable}};
+use crate::{shape::{Sphere, Ray}, intersection::Traceable};
 
 #[test]
 fn inside_sphere_intersect() {
     let sphere = Sphere { pos: Vec3::ZERO, radius: 5.0, material: Default::default() };
 
-    let ray = Ray { start: Vec3::ZERO, dir: Vec3::new(1.0, 0.0, -1.0).normalize() };
+    let ray = Ray { start: Vec3::ZERO, dir: Vec3::new(1.0, 0.0, -1.0).normalize(), time: 0.0 };
 
     let inter = sphere.ray_intersection(&ray).unwrap();
 
     println!("{:#?}", inter);
 }
+
+#[test]
+fn obj_negative_indices_and_quads() {
+    use crate::{obj::load_obj_file, material::MaterialKind};
+
+    let dir = std::env::temp_dir().join("raytracing_obj_test");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(dir.join("quad.mtl"), "newmtl red\nKd 1 0 0\n\nnewmtl glass\nNi 1.5\nd 0.2\n").unwrap();
+    std::fs::write(dir.join("quad.obj"), "\
+mtllib quad.mtl
+o floor
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+usemtl red
+f -4/-4 -3/-3 -2/-2 -1/-1
+g window
+usemtl glass
+f 1 2 3
+").unwrap();
+
+    let groups = load_obj_file(dir.join("quad.obj")).unwrap();
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].name, "floor");
+    assert_eq!(groups[0].triangles.len(), 2);
+    assert_eq!(groups[1].name, "window");
+
+    let t = &groups[0].triangles[1];
+    assert_eq!(t.p2.pos, Vec3::new(0.0, 0.0, 1.0));
+    assert_eq!(t.p2.tex, glam::Vec2::new(0.0, 1.0));
+    // Normal computed from the winding of the face
+    assert_eq!(t.p0.normal, Vec3::new(0.0, -1.0, 0.0));
+
+    assert!(matches!(groups[0].triangles[0].material.kind, MaterialKind::Lambertian { albedo } if albedo.r == 1.0 && albedo.g == 0.0));
+    assert!(matches!(groups[1].triangles[0].material.kind, MaterialKind::Transparent { refraction_index } if refraction_index == 1.5));
+
+    // Truncated lines are errors rather than zero-filled, optional values are not
+    std::fs::write(dir.join("short.obj"), "v 0 0 0 1\nvt 0.5 0.5\nv 1 2\n").unwrap();
+    let error = load_obj_file(dir.join("short.obj")).unwrap_err();
+    assert!(matches!(&error, crate::mesh::MeshError::Parse { line: 3, .. }), "{}", error);
+    assert!(error.to_string().ends_with("expected 3 to 4 numbers"), "{}", error);
+
+    std::fs::write(dir.join("short.mtl"), "newmtl grey\nKd 0.5\nKs 0.5 0.5\n").unwrap();
+    std::fs::write(dir.join("short.obj"), "mtllib short.mtl\n").unwrap();
+    let error = load_obj_file(dir.join("short.obj")).unwrap_err();
+    assert!(matches!(&error, crate::mesh::MeshError::Parse { line: 3, .. }), "{}", error);
+}
+
+#[test]
+fn obj_bump_maps_tilt_normals() {
+    use crate::{obj::load_obj_file, texture::Texture};
+
+    let dir = std::env::temp_dir().join("raytracing_obj_bump_test");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Flat heights give the normal straight out of the surface
+    let flat = Texture::from_height_map(&image::RgbImage::from_pixel(4, 4, image::Rgb([ 100, 100, 100 ])), 1.0);
+    assert_eq!(flat.data.get_pixel(1, 2).0, [ 128, 128, 255 ]);
+
+    // Heights rising along u
+    image::RgbImage::from_fn(16, 16, |x, _| image::Rgb([ (x * 16) as u8; 3 ])).save(dir.join("ramp.png")).unwrap();
+    std::fs::write(dir.join("bumpy.mtl"), "newmtl flat\nKs 1 1 1\nillum 3\n\nnewmtl bumpy\nKs 1 1 1\nillum 3\nmap_Bump -bm 4 ramp.png\n").unwrap();
+    std::fs::write(dir.join("bumpy.obj"), "\
+mtllib bumpy.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+usemtl flat
+f 1/1 2/2 3/3 4/4
+usemtl bumpy
+f 1/1 2/2 3/3 4/4
+").unwrap();
+
+    let groups = load_obj_file(dir.join("bumpy.obj")).unwrap();
+    let ray = Ray { start: Vec3::new(0.4, 0.3, -1.0), dir: Vec3::Z, time: 0.0 };
+
+    // Metals reflect around the shading normal
+    let reflected = |triangle: &crate::shape::Triangle| {
+        let inter = triangle.ray_intersection(&ray).unwrap();
+        triangle.material.scatter(&ray, &inter).ray.unwrap().dir
+    };
+
+    assert!(reflected(&groups[0].triangles[0]).abs_diff_eq(Vec3::NEG_Z, 1e-5));
+
+    // The surface rises towards +x, so its normal leans towards -x
+    let bumpy = reflected(&groups[0].triangles[2]);
+    assert!(bumpy.x < -0.1, "{}", bumpy);
+}
+
+#[test]
+fn scene_file_loads_objects_lights_and_instances() {
+    use image::{Rgb, codecs::hdr::HdrEncoder};
+    use crate::scene_file::parse_scene;
+
+    let dir = std::env::temp_dir().join("raytracing_scene_file_test");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(dir.join("tile.obj"), "v 0 0 0\nv 1 0 0\nv 0 0 1\nf 1 2 3\n").unwrap();
+
+    let pixels = vec![Rgb([0.5f32, 0.5, 0.5]); 8 * 4];
+    HdrEncoder::new(std::fs::File::create(dir.join("sky.hdr")).unwrap()).encode(&pixels, 8, 4).unwrap();
+
+    let source = r#"
+[camera]
+position = [0.0, 1.0, -5.0]
+look_at = [0.0, 0.0, 0.0]
+
+[sky]
+direction = [0.0, 1.0, 0.0]
+turbidity = 4.0
+
+[environment]
+path = "sky.hdr"
+intensity = 2.0
+
+[materials.white]
+kind = "lambertian"
+
+[[point_light]]
+position = [0.0, 10.0, 0.0]
+power = 100.0
+
+[[sphere]]
+position = [0.0, 1.0, 0.0]
+radius = 1.0
+material = "white"
+
+[[plane]]
+position = [0.0, 0.0, 0.0]
+normal = [0.0, 2.0, 0.0]
+material = "white"
+
+[[mesh]]
+path = "tile.obj"
+translation = [0.0, 0.0, 10.0]
+motion = [ { time = 0.0 }, { time = 1.0, translation = [4.0, 0.0, 0.0] } ]
+
+[meshes.tile]
+path = "tile.obj"
+material = "white"
+
+[[instance]]
+mesh = "tile"
+translation = [3.0, 0.0, 0.0]
+
+[[instance]]
+mesh = "tile"
+translation = [-3.0, 0.0, 0.0]
+scale = 2.0
+"#;
+
+    let scene = parse_scene(&dir.join("scene.toml"), source).unwrap();
+
+    assert!(scene.camera.orientation.mul_vec3(Vec3::Z).abs_diff_eq(Vec3::new(0.0, -1.0, 5.0).normalize(), 1e-6));
+    assert_eq!(scene.sky.sun_direction(), Vec3::Y);
+    assert_eq!(scene.sky.turbidity(), 4.0);
+    assert_eq!(scene.environment.as_ref().unwrap().radiance(Vec3::X).r, 1.0);
+
+    assert_eq!(scene.lights.len(), 1);
+    let sample = scene.lights[0].sample(Vec3::ZERO).unwrap();
+    assert!(sample.dir.abs_diff_eq(Vec3::Y, 1e-6) && (sample.distance - 10.0).abs() < 1e-4);
+
+    // The moving mesh is a single shape
+    assert_eq!(scene.shapes().len(), 5);
+
+    let down = |x: f32, z: f32, time: f32| Ray { start: Vec3::new(x, 5.0, z), dir: -Vec3::Y, time };
+    assert!(scene.shapes()[2].ray_intersection(&down(0.5, 10.1, 0.0)).is_some());
+    assert!(scene.shapes()[2].ray_intersection(&down(0.5, 10.1, 1.0)).is_none());
+    assert!(scene.shapes()[2].ray_intersection(&down(4.5, 10.1, 1.0)).is_some());
+
+    // The second instance is scaled around its translation
+    assert!(scene.shapes()[3].ray_intersection(&down(3.5, 0.1, 0.0)).is_some());
+    assert!(scene.shapes()[4].ray_intersection(&down(-1.5, 0.1, 0.0)).is_some());
+    assert!(scene.shapes()[4].ray_intersection(&down(-0.5, 0.1, 0.0)).is_none());
+}
+
+#[test]
+fn scene_file_errors_point_at_their_location() {
+    use std::path::Path;
+    use crate::scene_file::parse_scene;
+
+    let location = |source: &str| parse_scene(Path::new("scene.toml"), source).unwrap_err().location;
+
+    let camera = "[camera]\nposition = [0.0, 0.0, 0.0]\n";
+    let light = "\n[light]\ndirection = [0.0, 1.0, 0.0]\n";
+
+    assert_eq!(location(&format!("{}fov = 90.0\nzoom = 2.0\n{}", camera, light)), Some((4, 1)));
+
+    assert_eq!(location(&format!("{}\n[[sphere]]\nposition = [0.0, 0.0, 0.0]\nradius = 1.0\nmaterial = \"missing\"\n{}", camera, light)), Some((7, 12)));
+
+    assert_eq!(location(&format!("{}{}\n[sky]\nelevation = 30.0\n", camera, light)), Some((7, 1)));
+
+    let error = parse_scene(Path::new("scene.toml"), "[camera]\n").unwrap_err();
+    assert!(error.to_string().starts_with("scene.toml:1:1: "), "{}", error);
+}
+
+#[test]
+fn stl_ascii_and_binary() {
+    use crate::{stl::load_stl_file, mesh::MeshError};
+
+    let dir = std::env::temp_dir().join("raytracing_stl_test");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(dir.join("ascii.stl"), "\
+solid test
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid test
+").unwrap();
+
+    let triangles = load_stl_file(dir.join("ascii.stl")).unwrap();
+    assert_eq!(triangles.len(), 1);
+    assert_eq!(triangles[0].p0.normal, Vec3::Z); // Recomputed from the winding
+
+    // Binary file whose header starts with "solid", like many exporters write
+    let mut binary = b"solid exported".to_vec();
+    binary.resize(80, 0);
+    binary.extend(1u32.to_le_bytes());
+    for f in [ 0.0f32, 1.0, 0.0,  0.0, 0.0, 0.0,  0.0, 0.0, 1.0,  1.0, 0.0, 0.0 ] {
+        binary.extend(f.to_le_bytes());
+    }
+    binary.extend([0, 0]);
+    std::fs::write(dir.join("binary.stl"), &binary).unwrap();
+
+    let triangles = load_stl_file(dir.join("binary.stl")).unwrap();
+    assert_eq!(triangles.len(), 1);
+    assert_eq!(triangles[0].p2.pos, Vec3::X);
+    assert_eq!(triangles[0].p0.normal, Vec3::Y);
+
+    binary.truncate(binary.len() - 10);
+    binary[0] = b'x';
+    std::fs::write(dir.join("truncated.stl"), &binary).unwrap();
+
+    assert!(matches!(load_stl_file(dir.join("truncated.stl")), Err(MeshError::Invalid { .. })));
+}
+
+#[test]
+fn gltf_mirrors_x_and_converts_cameras() {
+    use crate::{gltf_file::load_gltf_file, scene_file::parse_scene, camera::FieldOfView};
+
+    let dir = std::env::temp_dir().join("raytracing_gltf_test");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // One triangle in front of a camera looking down -Z
+    std::fs::write(dir.join("triangle.gltf"), r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [ { "nodes": [0, 1] } ],
+        "nodes": [ { "mesh": 0, "translation": [0, 0, -2] }, { "camera": 0, "translation": [0, 0, 5] } ],
+        "cameras": [ { "type": "perspective", "perspective": { "yfov": 1.0, "znear": 0.1 } } ],
+        "meshes": [ { "primitives": [ { "attributes": { "POSITION": 0 } } ] } ],
+        "accessors": [ { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] } ],
+        "bufferViews": [ { "buffer": 0, "byteLength": 36 } ],
+        "buffers": [ { "byteLength": 36, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA" } ]
+    }"#).unwrap();
+
+    let scene = load_gltf_file(dir.join("triangle.gltf")).unwrap();
+
+    assert_eq!(scene.triangles.len(), 1);
+    assert_eq!(scene.triangles[0].p1.pos, Vec3::new(-1.0, 0.0, -2.0));
+
+    let camera = &scene.cameras[0].camera;
+    assert_eq!(camera.position, Vec3::new(0.0, 0.0, 5.0));
+    assert!(camera.orientation.mul_vec3(Vec3::Z).abs_diff_eq(-Vec3::Z, 1e-6));
+    // Right of the image is +X in glTF, so -X once mirrored
+    assert!(camera.orientation.mul_vec3(Vec3::X).abs_diff_eq(-Vec3::X, 1e-6));
+
+    // Scene files take the camera from the file, with their own fields on top
+    let light = "\n[light]\ndirection = [0.0, 1.0, 0.0]\n";
+    let imported = parse_scene(&dir.join("scene.toml"), &format!("[camera]\nfrom = \"triangle.gltf\"\n{}", light)).unwrap();
+    assert_eq!(imported.camera.position, camera.position);
+    assert_eq!(imported.camera.orientation, camera.orientation);
+    assert_eq!(imported.camera.fov, FieldOfView::Vertical(1.0));
+
+    let moved = parse_scene(&dir.join("scene.toml"), &format!("[camera]\nfrom = \"triangle.gltf\"\nposition = [1.0, 2.0, 3.0]\nfov = 60.0\n{}", light)).unwrap();
+    assert_eq!(moved.camera.position, Vec3::new(1.0, 2.0, 3.0));
+    assert_eq!(moved.camera.orientation, camera.orientation);
+    assert_eq!(moved.camera.fov, FieldOfView::Vertical(60.0f32.to_radians()));
+
+    let error = parse_scene(&dir.join("scene.toml"), &format!("[camera]\nfrom = \"triangle.gltf\"\nname = \"missing\"\n{}", light)).unwrap_err();
+    assert_eq!(error.location, Some((2, 8)));
+}
+
+#[test]
+fn ply_ascii_and_big_endian() {
+    use crate::ply::load_ply_file;
+
+    let dir = std::env::temp_dir().join("raytracing_ply_test");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(dir.join("ascii.ply"), "\
+ply
+format ascii 1.0
+comment quad with colors
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+").unwrap();
+
+    let triangles = load_ply_file(dir.join("ascii.ply")).unwrap();
+    assert_eq!(triangles.len(), 2);
+    assert_eq!(triangles[0].p0.normal, Vec3::Z);
+
+    let colors = triangles[0].colors.unwrap();
+    assert_eq!(( colors[1].r, colors[1].g, colors[1].b ), ( 0.0, 1.0, 0.0 ));
+    assert_eq!(triangles[1].colors.unwrap()[2].b, 1.0);
+
+    let mut binary = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\nproperty double x\nproperty double y\nproperty double z\nelement face 1\nproperty list uchar ushort vertex_index\nend_header\n".to_vec();
+    for f in [ 0.0f64, 0.0, 0.0,  0.0, 2.0, 0.0,  0.0, 0.0, 3.0 ] {
+        binary.extend(f.to_be_bytes());
+    }
+    binary.push(3);
+    for i in [ 0u16, 1, 2 ] {
+        binary.extend(i.to_be_bytes());
+    }
+    std::fs::write(dir.join("binary.ply"), &binary).unwrap();
+
+    let triangles = load_ply_file(dir.join("binary.ply")).unwrap();
+    assert_eq!(triangles.len(), 1);
+    assert_eq!(triangles[0].p2.pos, Vec3::new(0.0, 0.0, 3.0));
+    assert!(triangles[0].colors.is_none());
+}
+
+#[test]
+fn triangle_interpolates_vertex_normals() {
+    use crate::{shape::{Triangle, Vertex}, material::Material};
+
+    let vertex = |pos: Vec3, normal: Vec3| Vertex { pos, normal: normal.normalize(), tex: glam::Vec2::ZERO };
+    let triangle = Triangle::new(
+        vertex(Vec3::ZERO, Vec3::new(-1.0, 0.0, 1.0)),
+        vertex(Vec3::X, Vec3::new(1.0, 0.0, 1.0)),
+        vertex(Vec3::Y, Vec3::Z),
+        Material::default()
+    );
+
+    // Halfway between the first two vertices, the normals cancel out along x
+    let ray = Ray { start: Vec3::new(0.5, 0.0, 1.0), dir: -Vec3::Z, time: 0.0 };
+    let inter = triangle.ray_intersection(&ray).unwrap();
+
+    assert!(inter.normal.abs_diff_eq(Vec3::Z, 1e-5));
+    assert_eq!(inter.geometric_normal, Vec3::Z);
+
+    // Hit from behind, both normals are flipped towards the ray
+    let ray = Ray { start: Vec3::new(0.1, 0.1, -1.0), dir: Vec3::Z, time: 0.0 };
+    let inter = triangle.ray_intersection(&ray).unwrap();
+    assert!(!inter.front && inter.normal.z < 0.0 && inter.geometric_normal == -Vec3::Z);
+
+    let flat = triangle.set_flat_shading(true);
+    let ray = Ray { start: Vec3::new(0.1, 0.1, 1.0), dir: -Vec3::Z, time: 0.0 };
+    assert_eq!(flat.ray_intersection(&ray).unwrap().normal, Vec3::Z);
+}
+
+#[test]
+fn triangle_samples_uv_in_any_orientation() {
+    use glam::{Vec2, Quat};
+    use crate::{shape::{Triangle, Vertex, square}, material::Material};
+
+    let uvs = [ Vec2::ZERO, Vec2::X, Vec2::Y ];
+    let check = |triangle: &Triangle| {
+        for (vertex, uv) in [ triangle.p0, triangle.p1, triangle.p2 ].iter().zip(uvs) {
+            assert!(triangle.sample(vertex.pos).abs_diff_eq(uv, 1e-5));
+        }
+        let centroid = (triangle.p0.pos + triangle.p1.pos + triangle.p2.pos) / 3.0;
+        assert!(triangle.sample(centroid).abs_diff_eq(Vec2::splat(1.0 / 3.0), 1e-5));
+    };
+
+    let planes = [
+        [ Vec3::ZERO, Vec3::X, Vec3::Y ], // XY
+        [ Vec3::ZERO, Vec3::Y, Vec3::Z ], // YZ
+        [ Vec3::ZERO, Vec3::X, Vec3::Z ], // XZ
+        [ Vec3::new(1.0, 2.0, 3.0), Vec3::new(-2.0, 0.5, 1.0), Vec3::new(0.5, -1.0, -2.0) ]
+    ];
+    for [a, b, c] in planes {
+        let vertex = |pos: Vec3, tex: Vec2| Vertex { pos, normal: Vec3::ZERO, tex };
+        check(&Triangle::new(vertex(a, uvs[0]), vertex(b, uvs[1]), vertex(c, uvs[2]), Material::default()));
+    }
+
+    // Floor squares lie in the XZ plane
+    let ( floor, _ ) = square(Vec3::new(0.0, -1.0, 5.0), Vec2::splat(2.0), Quat::IDENTITY, Material::default());
+    let ray = Ray { start: Vec3::new(-0.5, 0.0, 4.5), dir: -Vec3::Y, time: 0.0 };
+    let inter = floor.ray_intersection(&ray).unwrap();
+    assert!(floor.sample(inter.point).abs_diff_eq(Vec2::new(0.25, 0.75), 1e-5));
+}
+
+#[test]
+fn thin_lens_rays_converge_on_focus_plane() {
+    use glam::Quat;
+    use crate::camera::{Camera, Aperture, pixel_as_ray};
+
+    let ( width, height ) = ( 4, 2 );
+    let pinhole = Camera::new(Vec3::new(1.0, 2.0, 3.0), Quat::from_rotation_y(0.3));
+    assert_eq!(pixel_as_ray(width, height, &pinhole, 1.0, 0.5, 0.0).unwrap().start, pinhole.position);
+
+    for shape in [ Aperture::Circle, Aperture::Polygon { blades: 6, rotation: 0.2 } ] {
+        let camera = Camera::new(pinhole.position, pinhole.orientation)
+            .set_aperture(0.5)
+            .set_aperture_shape(shape)
+            .set_focus_distance(10.0);
+
+        let focus = pinhole.position + pinhole.orientation * Vec3::new(-1.0, 0.5, 1.0) * 10.0;
+        for _ in 0..32 {
+            let ray = pixel_as_ray(width, height, &camera, 1.0, 0.5, 0.0).unwrap();
+            assert!(ray.start.distance(camera.position) <= 0.5 + 1e-5);
+
+            // Every ray crosses the focus plane at the same point
+            let t = (focus - ray.start).dot(camera.orientation * Vec3::Z) / ray.dir.dot(camera.orientation * Vec3::Z);
+            assert!((ray.start + ray.dir * t).abs_diff_eq(focus, 1e-4));
+        }
+    }
+}
+
+#[test]
+fn camera_projections() {
+    use std::f32::consts::{PI, FRAC_PI_2, FRAC_PI_4};
+    use glam::Quat;
+    use crate::camera::{Camera, Projection, pixel_as_ray};
+
+    let ( width, height ) = ( 200, 100 );
+    let camera = |projection| Camera::new(Vec3::new(0.0, 1.0, 0.0), Quat::IDENTITY).set_projection(projection);
+
+    // Parallel rays spread over the view rectangle
+    let ortho = camera(Projection::Orthographic { height: 4.0 });
+    let ray = pixel_as_ray(width, height, &ortho, 200.0, 0.0, 0.0).unwrap();
+    assert!(ray.start.abs_diff_eq(Vec3::new(4.0, 3.0, 0.0), 1e-5));
+    assert_eq!(ray.dir, Vec3::Z);
+
+    // Longitude from -180° to 180° along x, latitude from 90° to -90° along y
+    let panorama = camera(Projection::Equirectangular);
+    let dir = |x, y| pixel_as_ray(width, height, &panorama, x, y, 0.0).unwrap().dir;
+    assert!(dir(100.0, 50.0).abs_diff_eq(Vec3::Z, 1e-5));
+    assert!(dir(150.0, 50.0).abs_diff_eq(Vec3::X, 1e-5));
+    assert!(dir(0.0, 50.0).abs_diff_eq(-Vec3::Z, 1e-5));
+    assert!(dir(100.0, 0.0).abs_diff_eq(Vec3::Y, 1e-5));
+    let ( longitude, latitude ) = ( -PI / 2.0, FRAC_PI_4 );
+    assert!(dir(50.0, 25.0).abs_diff_eq(Vec3::new(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos()), 1e-5));
+
+    // The angle to the axis is proportional to the distance to the center
+    let fisheye = camera(Projection::Fisheye { fov: PI });
+    let dir = |x, y| pixel_as_ray(width, height, &fisheye, x, y, 0.0).unwrap().dir;
+    assert!(dir(100.0, 0.0).abs_diff_eq(Vec3::Y, 1e-5));
+    assert!(dir(75.0, 50.0).abs_diff_eq(Vec3::new(-FRAC_PI_4.sin(), 0.0, FRAC_PI_4.cos()), 1e-5));
+    assert!((dir(100.0 + 30.0, 50.0 - 40.0).angle_between(Vec3::Z) - FRAC_PI_2).abs() < 1e-5);
+
+    // Corners are outside of the image circle
+    assert!(pixel_as_ray(width, height, &fisheye, 100.0 + 31.0, 50.0 - 41.0, 0.0).is_none());
+    assert!(pixel_as_ray(width, height, &fisheye, 0.0, 0.0, 0.0).is_none());
+}
+
+#[test]
+fn look_at_camera_and_field_of_view() {
+    use crate::camera::{Camera, FieldOfView, pixel_as_ray};
+
+    let eye = Vec3::new(1.0, 2.0, 3.0);
+    let target = Vec3::new(4.0, 2.0, 7.0);
+    let camera = Camera::look_at(eye, target, Vec3::Y).set_fov(FieldOfView::Vertical(60.0_f32.to_radians()));
+
+    let ( width, height ) = ( 200, 100 );
+    let forward = (target - eye).normalize();
+    assert!(pixel_as_ray(width, height, &camera, 100.0, 50.0, 0.0).unwrap().dir.abs_diff_eq(forward, 1e-5));
+
+    // The top edge is half the vertical fov above the view direction, and the right of the image is on the +X side when looking down +Z
+    let top = pixel_as_ray(width, height, &camera, 100.0, 0.0, 0.0).unwrap().dir;
+    assert!((top.angle_between(forward) - 30.0_f32.to_radians()).abs() < 1e-5 && top.y > 0.0);
+    let right = pixel_as_ray(width, height, &camera, 200.0, 50.0, 0.0).unwrap().dir;
+    assert!(right.dot(Vec3::Y.cross(forward)) > 0.0);
+    assert!((right.angle_between(forward) - (2.0 * 30.0_f32.to_radians().tan()).atan()).abs() < 1e-5);
+
+    // A 36mm sensor behind a 18mm lens sees 90° across
+    let wide = camera.set_focal_length(18.0, 36.0);
+    let left = pixel_as_ray(width, height, &wide, 0.0, 50.0, 0.0).unwrap().dir;
+    assert!((left.angle_between(forward) - 45.0_f32.to_radians()).abs() < 1e-5);
+
+    // Looking straight down along `up` still gives a proper rotation
+    let down = Camera::look_at(Vec3::new(0.0, 10.0, 0.0), Vec3::ZERO, Vec3::Y);
+    assert!(down.orientation.is_normalized());
+    assert!(down.orientation.mul_vec3(Vec3::Z).abs_diff_eq(-Vec3::Y, 1e-5));
+}
+
+#[test]
+fn moving_sphere_follows_ray_time() {
+    use crate::{shape::Shape, motion::{Moving, Motion, Transform}};
+
+    let sphere = Sphere { pos: Vec3::ZERO, radius: 1.0, material: Default::default() };
+    let moving = Moving::new(sphere, Motion::linear(Transform::default(), Transform::from_translation(Vec3::new(4.0, 0.0, 0.0))));
+
+    let ray = |x: f32, time: f32| Ray { start: Vec3::new(x, 0.0, -5.0), dir: Vec3::Z, time };
+
+    assert!(moving.ray_intersection(&ray(0.0, 0.0)).is_some());
+    assert!(moving.ray_intersection(&ray(0.0, 1.0)).is_none());
+    assert!(moving.ray_intersection(&ray(4.0, 0.0)).is_none());
+
+    let inter = moving.ray_intersection(&ray(2.0, 0.5)).unwrap();
+    assert!(inter.point.abs_diff_eq(Vec3::new(2.0, 0.0, -1.0), 1e-5));
+    assert!(inter.normal.abs_diff_eq(-Vec3::Z, 1e-5));
+
+    // The bounds cover the sphere over the whole shutter interval
+    let bound = moving.bounding_box();
+    assert!(bound.min.abs_diff_eq(Vec3::splat(-1.0), 1e-5));
+    assert!(bound.max.abs_diff_eq(Vec3::new(5.0, 1.0, 1.0), 1e-5));
+}
+
+#[test]
+fn keyframed_camera_and_bezier_interpolation() {
+    use glam::Quat;
+    use crate::{camera::{Camera, pixel_as_ray}, motion::{Motion, Keyframe, Transform, Interpolation}};
+
+    let keyframes = |interpolation| Motion::new(vec![
+        Keyframe::new(0.0, Transform::default()).set_interpolation(interpolation),
+        Keyframe::new(2.0, Transform::from_translation(Vec3::X * 4.0)).set_interpolation(interpolation),
+        Keyframe::new(4.0, Transform { rotation: Quat::from_rotation_y(1.0), ..Transform::from_translation(Vec3::X * 4.0 + Vec3::Z * 4.0) })
+    ]);
+
+    let linear = keyframes(Interpolation::Linear);
+    assert!(linear.at(-1.0).translation.abs_diff_eq(Vec3::ZERO, 1e-6));
+    assert!(linear.at(0.5).translation.abs_diff_eq(Vec3::X, 1e-6));
+    assert!(linear.at(3.0).rotation.abs_diff_eq(Quat::from_rotation_y(0.5), 1e-6));
+    assert!(linear.at(5.0).translation.abs_diff_eq(Vec3::new(4.0, 0.0, 4.0), 1e-6));
+
+    // Eases out of the first keyframe, goes through the others and rounds the corner between them
+    let bezier = keyframes(Interpolation::Bezier);
+    assert!(bezier.at(0.5).translation.x < 1.0);
+    assert!(bezier.at(2.0).translation.abs_diff_eq(Vec3::X * 4.0, 1e-6));
+    assert!(bezier.at(1.5).translation.z < 0.0 && bezier.at(2.5).translation.x > 4.0);
+
+    // The camera follows its animation at the time of the frame
+    let ( width, height ) = ( 2, 2 );
+    let camera = Camera::new(Vec3::ZERO, Quat::IDENTITY).set_animation(linear);
+    let ray = pixel_as_ray(width, height, &camera, 1.0, 1.0, 3.0).unwrap();
+    assert!(ray.start.abs_diff_eq(Vec3::new(4.0, 0.0, 2.0), 1e-6));
+    assert!(ray.dir.abs_diff_eq(Quat::from_rotation_y(0.5) * Vec3::Z, 1e-6));
+}
+
+#[test]
+fn stereo_eyes_converge_and_go_around_in_panoramas() {
+    use glam::Quat;
+    use image::{RgbImage, Rgb};
+    use crate::{camera::{Camera, Projection, pixel_as_ray}, stereo::{StereoRig, StereoLayout, combine}};
+
+    let ( width, height ) = ( 200, 100 );
+    let camera = Camera::new(Vec3::new(0.0, 1.0, 0.0), Quat::IDENTITY);
+
+    // Eyes are on each side, and their central rays cross at the convergence distance
+    let [left, right] = StereoRig::new(0.5).set_convergence(10.0).unwrap().eyes(&camera);
+    let ( l, r ) = ( pixel_as_ray(width, height, &left, 100.0, 50.0, 0.0).unwrap(), pixel_as_ray(width, height, &right, 100.0, 50.0, 0.0).unwrap() );
+    assert!(l.start.abs_diff_eq(Vec3::new(-0.25, 1.0, 0.0), 1e-6) && r.start.abs_diff_eq(Vec3::new(0.25, 1.0, 0.0), 1e-6));
+    let focus = Vec3::new(0.0, 1.0, 10.0);
+    assert!((l.start + l.dir * 10.0 / l.dir.z).abs_diff_eq(focus, 1e-5));
+    assert!((r.start + r.dir * 10.0 / r.dir.z).abs_diff_eq(focus, 1e-5));
+
+    // Omnidirectional stereo: looking towards +X, the left eye is towards +Z
+    let panorama = camera.set_projection(Projection::Equirectangular);
+    let [left, right] = StereoRig::new(0.5).eyes(&panorama);
+    let ( l, r ) = ( pixel_as_ray(width, height, &left, 150.0, 50.0, 0.0).unwrap(), pixel_as_ray(width, height, &right, 150.0, 50.0, 0.0).unwrap() );
+    assert!(l.start.abs_diff_eq(Vec3::new(0.0, 1.0, 0.25), 1e-6) && r.start.abs_diff_eq(Vec3::new(0.0, 1.0, -0.25), 1e-6));
+    assert!(l.dir.abs_diff_eq(Vec3::X, 1e-6) && r.dir.abs_diff_eq(Vec3::X, 1e-6));
+
+    let eye = |c| RgbImage::from_pixel(2, 1, Rgb([c, c, c]));
+    let packed = combine(&eye(0), &eye(255), StereoLayout::TopBottom);
+    assert_eq!(packed.dimensions(), (2, 2));
+    assert_eq!(packed.get_pixel(1, 1).0, [255; 3]);
+}
+
+#[test]
+fn sun_sampling_and_bsdf_evaluation() {
+    use std::f32::consts::PI;
+    use crate::{light::Sun, material::{Material, Color}, shape::Plane};
+
+    let sun = Sun::new(Vec3::new(1.0, 1.0, 0.0), 0.1, Color::WHITE);
+    for _ in 0..64 {
+        assert!(sun.contains(sun.sample()));
+    }
+
+    let floor = |material| Plane { pos: Vec3::ZERO, normal: Vec3::Y, material };
+    let ray = Ray { start: Vec3::new(0.0, 1.0, -1.0), dir: Vec3::new(0.0, -1.0, 1.0).normalize(), time: 0.0 };
+
+    let diffuse = floor(Material::new_lambertian(Color::GRAY));
+    let inter = diffuse.ray_intersection(&ray).unwrap();
+    let bsdf = diffuse.material.eval(&ray, &inter, sun.direction).unwrap();
+    assert!((bsdf.g - 0.5 * sun.direction.y / PI).abs() < 1e-6);
+    assert_eq!(diffuse.material.eval(&ray, &inter, -Vec3::Y).unwrap().r, 0.0);
+
+    // Mirrors can only be lit through their reflection
+    let mirror = floor(Material::new_metal(Color::WHITE));
+    assert!(mirror.material.eval(&ray, &mirror.ray_intersection(&ray).unwrap(), sun.direction).is_none());
+}
+
+#[test]
+fn scatter_agrees_with_eval_and_pdf() {
+    use crate::{material::{Material, Color}, shape::Plane};
+
+    let ray = Ray { start: Vec3::new(0.0, 1.0, -1.0), dir: Vec3::new(0.0, -1.0, 1.0).normalize(), time: 0.0 };
+
+    for material in [ Material::new_lambertian(Color::new(0.2, 0.5, 0.8)), Material::new_rough_metal(Color::GRAY, 0.4) ] {
+        let floor = Plane { pos: Vec3::ZERO, normal: Vec3::Y, material };
+        let inter = floor.ray_intersection(&ray).unwrap();
+
+        for _ in 0..64 {
+            let scatter = floor.material.scatter(&ray, &inter);
+            let Some(scattered) = scatter.ray else { continue };
+
+            // The weight of a scattered ray is what light sampling would give for the same direction
+            let pdf = floor.material.pdf(&ray, &inter, scattered.dir);
+            let expected = floor.material.eval(&ray, &inter, scattered.dir).unwrap() / pdf;
+
+            assert!((scatter.pdf.unwrap() - pdf).abs() <= 1e-4 * pdf);
+            assert!((scatter.attenuation.r - expected.r).abs() <= 1e-4 && (scatter.attenuation.b - expected.b).abs() <= 1e-4);
+        }
+    }
+
+    let glass = Plane { pos: Vec3::ZERO, normal: Vec3::Y, material: Material::new_transparent(1.5) };
+    assert!(glass.material.scatter(&ray, &glass.ray_intersection(&ray).unwrap()).pdf.is_none());
+}
+
+#[test]
+fn lights_sample_their_irradiance() {
+    use std::f32::consts::PI;
+    use crate::{light::Light, material::Color};
+
+    let point = Vec3::ZERO;
+    let color = Color::WHITE;
+
+    // Irradiance on an upward facing point, estimated by sampling and in closed form
+    let irradiance = |light: &Light| {
+        let count = 20000;
+        (0..count).filter_map(|_| light.sample(point)).map(|s| {
+            let cos = s.dir.y.max(0.0);
+            s.radiance.r * cos / s.pdf.unwrap_or(1.0)
+        }).sum::<f32>() / count as f32
+    };
+
+    let bulb = Light::new_point(Vec3::new(0.0, 2.0, 0.0), color, 100.0);
+    assert!((irradiance(&bulb) - 100.0 / (4.0*PI) / 4.0).abs() < 1e-4);
+
+    // Spots are as bright as point lights of the same power inside their cone, and dark outside
+    let spot = |direction| Light::new_spot(Vec3::new(0.0, 2.0, 0.0), direction, 0.5, 0.0, color, 100.0);
+    assert!((irradiance(&spot(-Vec3::Y)) - irradiance(&bulb)).abs() < 1e-4);
+    assert!(spot(Vec3::X).sample(point).is_none());
+
+    let sphere = Light::new_sphere(Vec3::new(0.0, 5.0, 0.0), 1.0, color, 500.0);
+    let radiance = 500.0 / (PI * 4.0*PI);
+    assert!((irradiance(&sphere) - PI * radiance / 25.0).abs() < 0.01 * PI * radiance / 25.0);
+
+    // A disk right above the point covers the sky over an angle of 45°
+    let disk = Light::new_disk(Vec3::new(0.0, 1.0, 0.0), -Vec3::Y, 1.0, color, 100.0);
+    let radiance = 100.0 / (PI * PI);
+    assert!((irradiance(&disk) - PI * radiance * 0.5).abs() < 0.03 * PI * radiance * 0.5);
+    assert!(Light::new_disk(Vec3::new(0.0, 1.0, 0.0), Vec3::Y, 1.0, color, 100.0).sample(point).is_none());
+
+    // Scattered rays find area lights with the density of their sampling
+    let rect = Light::new_rect(Vec3::new(0.0, 3.0, 0.0), Vec3::X * 2.0, Vec3::Z * 2.0, color, 100.0);
+    for light in [ &rect, &disk, &sphere ] {
+        for _ in 0..16 {
+            let sample = light.sample(point).unwrap();
+            let hit = light.intersect(&Ray { start: point, dir: sample.dir, time: 0.0 }).unwrap();
+
+            assert!((hit.distance - sample.distance).abs() < 1e-3);
+            assert_eq!(hit.radiance.g, sample.radiance.g);
+            assert!((light.pdf(point, sample.dir, hit.distance) - sample.pdf.unwrap()).abs() < 1e-3 * sample.pdf.unwrap());
+        }
+    }
+    assert_eq!(rect.intersect(&Ray { start: Vec3::new(0.0, 5.0, 0.0), dir: -Vec3::Y, time: 0.0 }).unwrap().radiance.r, 0.0);
+}
+
+#[test]
+fn daylight_sky_and_sun_disc() {
+    use crate::{sky::Sky, material::Color};
+
+    let noon = Sky::from_angles(60f32.to_radians(), 0.0);
+    assert!(noon.sun_direction().abs_diff_eq(Vec3::new(0.0, 60f32.to_radians().sin(), 0.5), 1e-6));
+
+    // Blue overhead, brighter around the sun
+    let zenith = noon.radiance(Vec3::Y);
+    assert!(zenith.b > zenith.r);
+    let near_sun = noon.radiance((noon.sun_direction() + Vec3::new(0.0, 0.0, 0.1)).normalize());
+    assert!(near_sun.g > noon.radiance(Vec3::new(0.0, 0.5, -1.0).normalize()).g);
+
+    // The sun covers its angular size, and gets dimmer and redder as it sets
+    let sun = noon.sun();
+    let edge = 0.26f32.to_radians();
+    assert!(sun.contains(sun.direction));
+    assert!(sun.contains(Vec3::new(0.0, (60f32.to_radians() + edge).sin(), (60f32.to_radians() + edge).cos())));
+    assert!(!sun.contains(Vec3::new(0.0, (60f32.to_radians() + 2.0*edge).sin(), (60f32.to_radians() + 2.0*edge).cos())));
+    for _ in 0..64 {
+        assert!(sun.contains(sun.sample()));
+    }
+
+    let evening = Sky::from_angles(5f32.to_radians(), 0.0).sun();
+    assert!(evening.radiance.g < sun.radiance.g);
+    assert!(evening.radiance.r / evening.radiance.b > sun.radiance.r / sun.radiance.b);
+    assert_eq!(Sky::from_angles(-0.1, 0.0).sun().radiance.g, 0.0);
+
+    // The ground reflects the light it receives
+    let dark = noon.clone().set_ground_albedo(Color::BLACK);
+    assert_eq!(dark.radiance(-Vec3::Y).r, 0.0);
+    assert!(noon.radiance(-Vec3::Y).r > 0.0);
+    assert!(noon.clone().set_turbidity(8.0).radiance(-Vec3::Y).g != noon.radiance(-Vec3::Y).g);
+}
+
+#[test]
+fn environment_map_orientation_and_importance_sampling() {
+    use std::f32::consts::{PI, FRAC_PI_2};
+    use image::{Rgb32FImage, Rgb};
+    use crate::environment::EnvironmentMap;
+
+    let ( width, height ) = ( 16, 8 );
+    let mut data = Rgb32FImage::from_pixel(width, height, Rgb([0.1, 0.1, 0.1]));
+    data.put_pixel(8, 3, Rgb([1000.0, 500.0, 100.0])); // Just above the horizon, towards +Z
+
+    let map = EnvironmentMap::new(data.clone());
+    assert_eq!(map.radiance(Vec3::new(0.0, 0.1, 1.0).normalize()).r, 1000.0);
+    assert_eq!(map.radiance(-Vec3::Y).r, 0.1);
+
+    let turned = map.clone().set_rotation(FRAC_PI_2).set_intensity(2.0);
+    assert_eq!(turned.radiance(Vec3::new(1.0, 0.1, 0.0).normalize()).r, 2000.0);
+
+    // Most samples land on the bright pixel, and the estimate of the light coming from all around matches the image
+    let pixel_solid_angle = |y: u32| 2.0*PI / width as f32 * ((y as f32 / height as f32 * PI).cos() - ((y + 1) as f32 / height as f32 * PI).cos());
+    let exact: f32 = data.enumerate_pixels().map(|(_, y, p)| p.0[1] * pixel_solid_angle(y)).sum();
+
+    let count = 4000;
+    let mut estimate = 0.0;
+    let mut bright = 0;
+    for _ in 0..count {
+        let ( dir, pdf ) = turned.sample();
+        assert!((turned.pdf(dir) - pdf).abs() <= 1e-3 * pdf);
+
+        let radiance = turned.radiance(dir);
+        if radiance.g > 100.0 { bright += 1 }
+        estimate += radiance.g / pdf;
+    }
+
+    assert!(bright > count * 9 / 10);
+    assert!((estimate / count as f32 - 2.0 * exact).abs() < 0.01 * 2.0 * exact);
+}
+
+#[test]
+fn emissive_shapes_sample_towards_points() {
+    use std::f32::consts::PI;
+    use glam::Vec2;
+    use crate::{shape::{Triangle, Vertex}, material::{Material, Color}, motion::{Moving, Motion, Transform}};
+
+    let point = Vec3::ZERO;
+    let vertex = |x, z| Vertex { pos: Vec3::new(x, 1.0, z), normal: -Vec3::Y, tex: Vec2::ZERO };
+
+    // Faces down, towards the point
+    let lamp = Material::new_emmitive(Color::WHITE, 2.0).set_two_sided(false);
+    let triangle = Triangle::new(vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(-1.0, 1.0), lamp.clone());
+    let sphere = Sphere { pos: Vec3::new(0.0, 5.0, 0.0), radius: 1.0, material: lamp.clone() };
+
+    // Stretched unevenly half way through its motion
+    let end = Transform { translation: Vec3::new(1.0, 0.5, 0.0), scale: Vec3::new(3.0, 1.0, 0.5), ..Default::default() };
+    let moving = Moving::new(Triangle::new(vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(-1.0, 1.0), lamp.clone()), Motion::linear(Transform::default(), end));
+
+    // Scattered rays find the sampled points with the same density
+    for shape in [ &triangle as &dyn Traceable, &sphere, &moving ] {
+        for _ in 0..16 {
+            let ( sample, pdf ) = shape.sample_towards(point, 0.5).unwrap();
+            let dir = (sample.point - point).normalize();
+            let hit = shape.ray_intersection(&Ray { start: point, dir, time: 0.5 }).unwrap();
+
+            assert!(hit.point.abs_diff_eq(sample.point, 1e-3));
+            assert!((shape.pdf_towards(point, 0.5, &hit) - pdf).abs() < 1e-3 * pdf);
+            assert_eq!(sample.shape.material().unwrap().emitted(&sample).unwrap().g, 2.0);
+        }
+    }
+
+    // Hits within the moving shape point back at it, through which it is sampled
+    let hit = moving.ray_intersection(&Ray { start: point, dir: Vec3::Y, time: 0.5 }).unwrap();
+    assert_eq!(crate::intersection::address(hit.parent.unwrap()), crate::intersection::address(&moving));
+
+    // Irradiance of the sphere on an upward facing point, against its closed form
+    let count = 20000;
+    let estimate = (0..count).map(|_| {
+        let ( sample, pdf ) = sphere.sample_towards(point, 0.0).unwrap();
+        sphere.material.emitted(&sample).unwrap().r * (sample.point - point).normalize().y / pdf
+    }).sum::<f32>() / count as f32;
+    assert!((estimate - PI * 2.0 / 25.0).abs() < 0.01 * PI * 2.0 / 25.0);
+
+    // One sided emitters are black from behind
+    let ( back, _ ) = triangle.sample_towards(Vec3::new(0.0, 3.0, 0.0), 0.0).unwrap();
+    assert_eq!(triangle.material.emitted(&back).unwrap().r, 0.0);
+    assert!(sphere.sample_towards(sphere.pos, 0.0).is_none());
+    assert!(Material::new_lambertian(Color::WHITE).emitted(&back).is_none());
+}
+
+/// Small triangles scattered in a cube from `-spread` to `spread`, the same for a given seed
+fn random_triangles(seed: u64, n: usize, spread: f32) -> Vec<crate::shape::Triangle> {
+    use glam::Vec2;
+    use crate::{shape::{Triangle, Vertex}, rng::random};
+
+    crate::rng::seed(seed);
+    let point = || (Vec3::new(random::<f32>(), random::<f32>(), random::<f32>()) * 2.0 - 1.0) * spread;
+    let vertex = |pos| Vertex { pos, normal: Vec3::Y, tex: Vec2::ZERO };
+
+    (0..n).map(|_| {
+        let center = point();
+        Triangle::new(vertex(center), vertex(center + point() * 0.05), vertex(center + point() * 0.05), Default::default())
+    }).collect()
+}
+
+/// Distance to the closest hit of a ray, testing every shape
+fn brute_force_closest(shapes: &[Box<dyn Traceable>], ray: &Ray) -> Option<f32> {
+    shapes.iter()
+        .filter_map(|shape| shape.ray_intersection(ray))
+        .map(|inter| inter.point.distance(ray.start))
+        .min_by(f32::total_cmp)
+}
+
+/// Owns shapes as trait objects, the way scenes do
+fn boxed<T: Traceable + 'static>(shapes: Vec<T>) -> Vec<Box<dyn Traceable>> {
+    shapes.into_iter().map(|shape| Box::new(shape) as Box<dyn Traceable>).collect()
+}
+
+#[test]
+fn surface_area_heuristic_bvh() {
+    use glam::Vec2;
+    use crate::{bvh::Bvh, shape::{Triangle, Vertex}, rng::random};
+
+    // Small triangles scattered in a cube and a few spheres
+    let mut shapes = boxed(random_triangles(7, 500, 10.0));
+    let point = || Vec3::new(random::<f32>(), random::<f32>(), random::<f32>()) * 20.0 - 10.0;
+    shapes.extend(boxed((0..20).map(|_| Sphere { pos: point(), radius: 0.5, material: Default::default() }).collect()));
+
+    // Stacked triangles no split can separate
+    let vertex = |pos| Vertex { pos, normal: Vec3::Y, tex: Vec2::ZERO };
+    let stacked = || Box::new(Triangle::new(vertex(Vec3::ZERO), vertex(Vec3::X), vertex(Vec3::Y), Default::default())) as Box<dyn Traceable>;
+    shapes.extend([ stacked(), stacked(), stacked() ]);
+
+    let shapes_ref: Vec<_> = shapes.iter().map(Box::as_ref).collect();
+    let median = Bvh::construct(shapes_ref.clone(), 0);
+    let sah = Bvh::build(shapes_ref);
+
+    assert!(sah.cost() < median.cost());
+    assert!(sah.cost().is_finite());
+
+    // Leaves hold a few primitives each, and all of them
+    let sizes: Vec<usize> = sah.leaves().map(<[_]>::len).collect();
+    assert_eq!(sizes.iter().sum::<usize>(), shapes.len());
+    assert!(sizes.iter().all(|&size| (1..=4).contains(&size)));
+    assert!(sizes.iter().any(|&size| size > 1));
+
+    // Both find the closest hit of a brute force search
+    for _ in 0..200 {
+        let ray = Ray { start: point() * 2.0, dir: (point() - point()).normalize(), time: 0.0 };
+
+        let expected = brute_force_closest(&shapes, &ray);
+        for bvh in [ &median, &sah ] {
+            assert_eq!(bvh.intersects(&ray).map(|inter| inter.point.distance(ray.start)), expected);
+        }
+    }
+}
+
+#[test]
+fn flattened_bvh_closest_and_any_hit() {
+    use crate::{bvh::Bvh, rng::random};
+
+    let shapes = boxed(random_triangles(11, 2000, 10.0));
+    let point = || Vec3::new(random::<f32>(), random::<f32>(), random::<f32>()) * 20.0 - 10.0;
+
+    let bvh = Bvh::build(shapes.iter().map(Box::as_ref).collect());
+    assert_eq!(bvh.leaves().map(<[_]>::len).sum::<usize>(), shapes.len());
+
+    for i in 0..500 {
+        // Directions aren't always normalized
+        let dir = (point() - point()).normalize() * if i % 2 == 0 { 1.0 } else { 3.0 };
+        let ray = Ray { start: point(), dir, time: 0.0 };
+
+        let closest = brute_force_closest(&shapes, &ray);
+        assert_eq!(bvh.intersects(&ray).map(|inter| inter.point.distance(ray.start)), closest);
+
+        // Shadow rays only see what lies before the light
+        let distance = random::<f32>() * 20.0;
+        assert_eq!(bvh.occluded(&ray, distance), closest.is_some_and(|hit| hit < distance));
+    }
+}
+
+#[test]
+fn unbounded_shapes_stay_out_of_the_bvh() {
+    use crate::{bvh::Bvh, intersection::Intersection, shape::{Plane, Rect, Shape}, rng::random};
+
+    // Infinite boxes are hit by every ray, but can't be weighed by their area
+    let infinite = Rect::infinite();
+    assert!(infinite.intersects(&Ray { start: Vec3::splat(1e6), dir: Vec3::new(0.3, -0.2, 0.9).normalize(), time: 0.0 }));
+    assert!(!infinite.is_finite());
+    assert_eq!(infinite.surface_area(), f32::INFINITY);
+    assert_eq!(Rect { min: Vec3::ZERO, max: Vec3::ONE }.union(infinite).surface_area(), f32::INFINITY);
+
+    let floor = Plane { pos: Vec3::new(0.0, -12.0, 0.0), normal: Vec3::Y, material: Default::default() };
+    let wall = Plane { pos: Vec3::new(12.0, 0.0, 0.0), normal: -Vec3::X, material: Default::default() };
+    assert!(!floor.bounding_box().is_finite());
+
+    let mut shapes = boxed(random_triangles(5, 300, 10.0));
+    let point = || Vec3::new(random::<f32>(), random::<f32>(), random::<f32>()) * 20.0 - 10.0;
+    shapes.push(Box::new(floor));
+    shapes.push(Box::new(wall));
+
+    let bvh = Bvh::build(shapes.iter().map(Box::as_ref).collect());
+
+    // The hierarchy keeps finite bounds and a finite cost, the planes being tested by every ray
+    assert_eq!(bvh.unbounded().len(), 2);
+    assert!(bvh.bound().is_finite());
+    assert!(bvh.cost().is_finite() && bvh.cost() > 2.0);
+    assert_eq!(bvh.leaves().map(<[_]>::len).sum::<usize>(), 300);
+
+    for _ in 0..300 {
+        let ray = Ray { start: point(), dir: (point() - point()).normalize(), time: 0.0 };
+
+        let closest = brute_force_closest(&shapes, &ray);
+        assert_eq!(bvh.intersects(&ray).map(|inter| inter.point.distance(ray.start)), closest);
+        assert_eq!(bvh.occluded(&ray, 5.0), closest.is_some_and(|hit| hit < 5.0));
+    }
+
+    // Planes alone make a hierarchy without nodes
+    let bvh = Bvh::build(vec![ shapes[300].as_ref() ]);
+    assert_eq!(bvh.depth(), 0);
+    assert!(bvh.intersects(&Ray { start: Vec3::ZERO, dir: -Vec3::Y, time: 0.0 }).is_some());
+    assert!(bvh.intersects(&Ray { start: Vec3::ZERO, dir: Vec3::Y, time: 0.0 }).is_none());
+}
+
+#[test]
+fn instances_share_their_mesh() {
+    use std::sync::Arc;
+    use glam::{Mat4, Quat};
+    use crate::{bvh::Bvh, mesh::Mesh, instance::Instance, shape::{Shape, Triangle}, rng::random};
+
+    // Same triangles twice, once shared by the instances and once copied into place
+    let mesh = Arc::new(Mesh::new(random_triangles(4, 200, 1.0)));
+    let random_vec = || Vec3::new(random::<f32>(), random::<f32>(), random::<f32>());
+    assert_eq!(mesh.len(), 200);
+
+    let transforms: Vec<Mat4> = (0..100).map(|_| Mat4::from_scale_rotation_translation(
+        random_vec() + 0.5,
+        Quat::from_euler(glam::EulerRot::YXZ, random::<f32>() * 6.0, random::<f32>() * 6.0, 0.0),
+        random_vec() * 40.0 - 20.0
+    )).collect();
+
+    let instances: Vec<Instance> = transforms.iter().map(|&transform| Instance::new(mesh.clone(), transform)).collect();
+    assert_eq!(Arc::strong_count(&mesh), 101);
+
+    let copies: Vec<Triangle> = transforms.iter()
+        .flat_map(|&transform| random_triangles(4, 200, 1.0).into_iter().map(move |t| t.transform(transform)))
+        .collect();
+
+    let top = Bvh::build(instances.iter().map(|instance| instance as &dyn Traceable).collect());
+    let flat = Bvh::build(copies.iter().map(|triangle| triangle as &dyn Traceable).collect());
+
+    for instance in &instances {
+        let bound = instance.bounding_box();
+        assert!(bound.is_finite() && (bound.max - bound.min).min_element() > 0.0);
+    }
+
+    for _ in 0..500 {
+        let ray = Ray { start: random_vec() * 60.0 - 30.0, dir: (random_vec() - 0.5).normalize(), time: 0.0 };
+
+        let expected = flat.intersects(&ray);
+        let hit = top.intersects(&ray);
+        assert_eq!(hit.is_some(), expected.is_some());
+
+        if let ( Some(hit), Some(expected) ) = ( hit, expected ) {
+            assert!(hit.point.abs_diff_eq(expected.point, 1e-3));
+            assert!(hit.geometric_normal.abs_diff_eq(expected.geometric_normal, 1e-3));
+            assert!(hit.normal.abs_diff_eq(expected.normal, 1e-3));
+        }
+
+        let distance = random::<f32>() * 40.0;
+        assert_eq!(top.occluded(&ray, distance), flat.occluded(&ray, distance));
+    }
+}
+
+#[test]
+fn emissive_instances_and_moving_meshes_light_the_scene() {
+    use image::{Rgb, codecs::hdr::HdrEncoder};
+    use std::sync::Arc;
+    use glam::{Mat4, Quat};
+    use crate::{
+        Renderer,
+        scene_file::parse_scene,
+        mesh::Mesh,
+        instance::Instance,
+        motion::{Moving, Motion, Transform},
+        shape::Triangle,
+        material::{Material, Color}
+    };
+
+    let dir = std::env::temp_dir().join("raytracing_emissive_instance_test");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(dir.join("lamp.obj"), "v -1 0 -1\nv 1 0 -1\nv 1 0 1\nv -1 0 1\nf 1 2 3 4\n").unwrap();
+    let pixels = vec![Rgb([0.0f32; 3]); 8 * 4];
+    HdrEncoder::new(std::fs::File::create(dir.join("black.hdr")).unwrap()).encode(&pixels, 8, 4).unwrap();
+
+    // The same lamp above a floor, placed as copied triangles, as an instance and as a mesh that moves without going anywhere
+    let scene = |lamp: &str| parse_scene(&dir.join("scene.toml"), &format!(r#"
+[camera]
+position = [0.0, 1.0, 0.0]
+look_at = [0.0, 0.0, 1.0]
+
+[environment]
+path = "black.hdr"
+
+[materials.white]
+kind = "lambertian"
+
+[materials.lamp]
+kind = "emmitive"
+intensity = 4.0
+
+[[plane]]
+position = [0.0, 0.0, 0.0]
+normal = [0.0, 1.0, 0.0]
+material = "white"
+
+[meshes.lamp]
+path = "lamp.obj"
+material = "lamp"
+
+{}
+translation = [0.0, 3.0, 2.0]
+rotation = [0.0, 30.0, 20.0]
+scale = 1.5
+"#, lamp)).unwrap();
+
+    // A single bounce only sees the lamp through light sampling
+    let mean = |lamp| {
+        let image = Renderer::new(8, 8).set_samples(64).set_max_depth(1).set_seed(Some(1)).render(&scene(lamp));
+        image.pixels().map(|p| p.0[1] as f32).sum::<f32>() / 64.0
+    };
+
+    let copied = mean("[[mesh]]\npath = \"lamp.obj\"\nmaterial = \"lamp\"");
+    let instanced = mean("[[instance]]\nmesh = \"lamp\"");
+    let moving = mean("[[mesh]]\npath = \"lamp.obj\"\nmaterial = \"lamp\"\nmotion = [ { time = 0.0 }, { time = 1.0 } ]");
+
+    assert!(copied > 20.0, "{}", copied);
+    assert!((instanced - copied).abs() < 0.03 * copied, "{} against {}", instanced, copied);
+    assert!((moving - copied).abs() < 0.03 * copied, "{} against {}", moving, copied);
+
+    // Scattered rays find the points sampled on unevenly scaled instances with the same density
+    let lamp = Material::new_emmitive(Color::WHITE, 1.0);
+    let mesh = Arc::new(Mesh::new(random_triangles(2, 20, 1.0).into_iter().map(|t| Triangle { material: lamp.clone(), ..t }).collect()));
+    let transform = Mat4::from_scale_rotation_translation(Vec3::new(3.0, 0.5, 1.0), Quat::from_rotation_x(0.5), Vec3::new(0.0, 5.0, 0.0));
+    let end = Transform { translation: Vec3::new(2.0, 0.0, 0.0), scale: Vec3::new(1.0, 2.0, 1.0), ..Default::default() };
+
+    let instance = Instance::new(mesh.clone(), transform);
+    let moving = Moving::new(Instance::new(mesh, transform), Motion::linear(Transform::default(), end));
+
+    for shape in [ &instance as &dyn Traceable, &moving ] {
+        assert!(shape.power() > 0.0);
+
+        for _ in 0..32 {
+            let ( sample, pdf ) = shape.sample_towards(Vec3::ZERO, 0.5).unwrap();
+            let hit = shape.ray_intersection(&Ray { start: Vec3::ZERO, dir: sample.point.normalize(), time: 0.5 }).unwrap();
+
+            // Triangles in front of the sampled one hide it
+            if hit.point.abs_diff_eq(sample.point, 1e-3) {
+                assert!((shape.pdf_towards(Vec3::ZERO, 0.5, &hit) - pdf).abs() < 1e-3 * pdf);
+            }
+        }
+    }
+}
+
+#[test]
+fn stereo_convergence_must_be_positive() {
+    use crate::stereo::{StereoRig, InvalidConvergence};
+
+    assert_eq!(StereoRig::new(0.065).set_convergence(0.0), Err(InvalidConvergence(0.0)));
+    assert!(StereoRig::new(0.065).set_convergence(f32::NAN).is_err());
+    assert_eq!(StereoRig::new(0.065).set_convergence(2.0).map(|rig| rig.convergence), Ok(2.0));
+}
+
+#[test]
+fn last_bounce_counts_sampled_lights_fully() {
+    use image::Rgb32FImage;
+    use crate::{Scene, Renderer, Camera, Light, Color, Material, shape::Plane, environment::EnvironmentMap};
+
+    // Black surroundings, so that only the wide light above lights the plane
+    let scene = Scene::new(Camera::look_at(Vec3::new(0.0, 0.5, 0.0), Vec3::ZERO, Vec3::Z))
+        .set_environment(EnvironmentMap::new(Rgb32FImage::new(4, 2)))
+        .add_light(Light::new_rect(Vec3::new(0.0, 1.0, 0.0), Vec3::new(8.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 8.0), Color::WHITE, 100.0))
+        .add_shape(Plane { pos: Vec3::ZERO, normal: Vec3::Y, material: Material::new_lambertian(Color::new(0.5, 0.5, 0.5)) });
+
+    let mean = |max_depth| {
+        let image = Renderer::new(8, 8).set_samples(256).set_max_depth(max_depth).set_seed(Some(1)).render(&scene);
+        image.pixels().map(|p| p.0[0] as f32).sum::<f32>() / 64.0
+    };
+
+    // A lone plane cannot light itself, so a single bounce sees as much light as two
+    let ( one, two ) = ( mean(1), mean(2) );
+    assert!((one - two).abs() < 0.02 * two, "{} against {}", one, two);
+}
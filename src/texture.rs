@@ -46,6 +46,30 @@ impl Texture {
         Ok(Texture::new(data))
     }
 
+    /// Converts a grayscale height map, as used for bump mapping, into a tangent space normal map
+    ///
+    /// `strength` is the height of white pixels, measured in pixels.
+    pub fn from_height_map(heights: &RgbImage, strength: f32) -> Self {
+        let ( width, height ) = heights.dimensions();
+        let at = |x: i64, y: i64| {
+            let pixel = heights.get_pixel(x.rem_euclid(width as i64) as u32, y.rem_euclid(height as i64) as u32);
+            (pixel[0] as f32 + pixel[1] as f32 + pixel[2] as f32) / (3.0 * 255.0) * strength
+        };
+
+        let data = RgbImage::from_fn(width, height, |x, y| {
+            let ( x, y ) = ( x as i64, y as i64 );
+            // Rows go down the image while v goes up
+            let du = (at(x + 1, y) - at(x - 1, y)) / 2.0;
+            let dv = (at(x, y - 1) - at(x, y + 1)) / 2.0;
+
+            let normal = glam::Vec3::new(-du, -dv, 1.0).normalize();
+            let encode = |c: f32| ((c + 1.0) / 2.0 * 255.0).round() as u8;
+            image::Rgb([ encode(normal.x), encode(normal.y), encode(normal.z) ])
+        });
+
+        Texture::new(data)
+    }
+
     /// Samples the texture from two u,v coordinates ranging from 0 to 1 and interpolates matching pixels with them
     pub fn sample(&self, u: f32, v: f32) -> Color {
         let ( u, v ) = match self.wrapping {